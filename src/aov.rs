@@ -0,0 +1,188 @@
+use crate::exr::{self, Channel};
+use crate::framebuffer::Framebuffer;
+use crate::ray::{PathSample, Ray};
+use crate::vector::Color;
+use std::fs::File;
+use std::io::{self, BufWriter};
+
+/// Arbitrary output variables rendered alongside the beauty image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aov {
+    /// Distance from the camera to the first hit.
+    Depth,
+    Normal,
+    Position,
+    Albedo,
+    MaterialId,
+    ObjectId,
+    Direct,
+    Indirect,
+}
+
+pub static ALL_AOVS: [Aov; 8] = [
+    Aov::Depth,
+    Aov::Normal,
+    Aov::Position,
+    Aov::Albedo,
+    Aov::MaterialId,
+    Aov::ObjectId,
+    Aov::Direct,
+    Aov::Indirect,
+];
+
+impl Aov {
+    pub fn parse(name: &str) -> Option<Aov> {
+        ALL_AOVS.iter().copied().find(|aov| aov.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Albedo => "albedo",
+            Aov::MaterialId => "material",
+            Aov::ObjectId => "object",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    fn channel_names(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::MaterialId | Aov::ObjectId => &["id"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+        }
+    }
+
+    /// Depth and ids are not averaged: a blend of two ids or of foreground
+    /// and background depth is meaningless, so the first sample is kept.
+    fn is_filtered(&self) -> bool {
+        !matches!(self, Aov::Depth | Aov::MaterialId | Aov::ObjectId)
+    }
+
+    /// Value of this variable for one camera sample. Single channel
+    /// variables are stored in every component.
+    fn value(&self, sample: &PathSample, ray: &Ray) -> Color {
+        let hit = sample.first_hit.as_ref();
+        let scalar = |x: f64| Color::new(x, x, x);
+        match self {
+            Aov::Depth => scalar(hit.map_or(f64::INFINITY, |h| h.t * ray.dir.length())),
+            Aov::Normal => sample.normal(),
+            Aov::Position => hit.map_or(Color::default(), |h| h.p),
+            Aov::Albedo => sample.albedo(),
+            Aov::MaterialId => scalar(hit.map_or(-1.0, |h| h.material.kind() as f64)),
+            Aov::ObjectId => scalar(hit.map_or(-1.0, |h| h.object_id as f64)),
+            Aov::Direct => sample.direct,
+            Aov::Indirect => sample.indirect(),
+        }
+    }
+}
+
+struct AovBuffer {
+    aov: Aov,
+    sums: Vec<Color>,
+    counts: Vec<u32>,
+}
+
+/// Per pixel accumulation of the requested output variables.
+pub struct AovBuffers {
+    width: usize,
+    height: usize,
+    buffers: Vec<AovBuffer>,
+}
+
+impl AovBuffers {
+    pub fn new(width: usize, height: usize, aovs: &[Aov]) -> Self {
+        let buffers = aovs
+            .iter()
+            .map(|aov| AovBuffer {
+                aov: *aov,
+                sums: vec![Color::default(); width * height],
+                counts: vec![0; width * height],
+            })
+            .collect();
+
+        AovBuffers {
+            width,
+            height,
+            buffers,
+        }
+    }
+
+    /// Records a camera sample at raster position `(x, y)`, measured in
+    /// pixels from the top left corner of the image.
+    pub fn add_sample(&mut self, x: f64, y: f64, sample: &PathSample, ray: &Ray) {
+        let px = (x as usize).min(self.width - 1);
+        let py = (y as usize).min(self.height - 1);
+        let index = py * self.width + px;
+
+        for buffer in self.buffers.iter_mut() {
+            let value = buffer.aov.value(sample, ray);
+            if buffer.aov.is_filtered() {
+                buffer.sums[index] = buffer.sums[index] + value;
+            } else if buffer.counts[index] == 0 {
+                buffer.sums[index] = value;
+            }
+            buffer.counts[index] += 1;
+        }
+    }
+
+    pub fn resolve(&self, aov: Aov) -> Option<Framebuffer> {
+        let buffer = self.buffers.iter().find(|buffer| buffer.aov == aov)?;
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for (i, pixel) in framebuffer.pixels.iter_mut().enumerate() {
+            *pixel = if buffer.aov.is_filtered() && buffer.counts[i] > 0 {
+                buffer.sums[i] / buffer.counts[i] as f64
+            } else {
+                buffer.sums[i]
+            };
+        }
+        Some(framebuffer)
+    }
+
+    /// Writes each of `aovs` to its own `<prefix>.<name>.pfm` file.
+    pub fn write_pfm_files(&self, prefix: &str, aovs: &[Aov]) -> io::Result<()> {
+        for aov in aovs {
+            let path = format!("{}.{}.pfm", prefix, aov.name());
+            let mut out = BufWriter::new(File::create(path)?);
+            self.resolve(*aov).unwrap().write_pfm(&mut out)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the beauty image as `R`, `G`, `B` and each of `aovs` as its
+    /// own layer of a multi-layer EXR file.
+    pub fn write_exr(&self, path: &str, beauty: &Framebuffer, aovs: &[Aov]) -> io::Result<()> {
+        let mut channels = color_channels(beauty, "", &["R", "G", "B"]);
+        for aov in aovs {
+            let framebuffer = self.resolve(*aov).unwrap();
+            let prefix = format!("{}.", aov.name());
+            channels.extend(color_channels(&framebuffer, &prefix, aov.channel_names()));
+        }
+
+        let mut out = BufWriter::new(File::create(path)?);
+        exr::write_exr(&mut out, self.width, self.height, &mut channels)
+    }
+}
+
+fn color_channels(framebuffer: &Framebuffer, prefix: &str, names: &[&str]) -> Vec<Channel> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| Channel {
+            name: format!("{}{}", prefix, name),
+            samples: framebuffer
+                .pixels
+                .iter()
+                .map(|pixel| match i {
+                    0 => pixel.x as f32,
+                    1 => pixel.y as f32,
+                    _ => pixel.z as f32,
+                })
+                .collect(),
+        })
+        .collect()
+}
@@ -0,0 +1,143 @@
+use crate::distribution::Distribution2D;
+use crate::hdr::HdrImage;
+use crate::util::{self, PI};
+use crate::vector::{luminance, Vec3};
+use std::io;
+
+/// Shape of the opening of a lens, which out of focus highlights take on.
+/// Points on it are given relative to the aperture radius.
+pub enum Aperture {
+    Circle,
+    /// Regular polygon of straight `blades`, the first corner `rotation`
+    /// degrees anticlockwise from the right.
+    Polygon {
+        blades: usize,
+        rotation: f64,
+    },
+    /// Grey image covering the square around the unit circle.
+    Image(ApertureImage),
+}
+
+/// Transmission of an aperture mask, with white letting all light through.
+pub struct ApertureImage {
+    width: usize,
+    height: usize,
+    /// Rows from the top, scaled to at most 1.
+    transmission: Vec<f64>,
+    distribution: Distribution2D,
+}
+
+impl ApertureImage {
+    /// Fails for a mask that lets no light through.
+    pub fn new(image: &HdrImage) -> io::Result<ApertureImage> {
+        let mut transmission: Vec<f64> = image.pixels.iter().map(|p| luminance(*p)).collect();
+        let max = transmission.iter().cloned().fold(0.0, f64::max);
+        if max <= 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the aperture image is black",
+            ));
+        }
+        transmission.iter_mut().for_each(|t| *t /= max);
+        Ok(ApertureImage {
+            width: image.width,
+            height: image.height,
+            distribution: Distribution2D::new(&transmission, image.width, image.height),
+            transmission,
+        })
+    }
+}
+
+impl Aperture {
+    /// Random point on the aperture. Points are spread in proportion to
+    /// the transmission, so every ray carries the same weight.
+    pub fn sample(&self) -> (f64, f64) {
+        match self {
+            Aperture::Circle => {
+                let p = Vec3::random_in_unit_disk();
+                (p.x, p.y)
+            }
+            Aperture::Polygon { blades, rotation } => {
+                // Every blade edge makes a triangle of equal area with the centre
+                let u = util::random_double() * *blades as f64;
+                let edge = (u as usize).min(blades - 1);
+                let corner = |i: usize| {
+                    let angle =
+                        util::degrees_to_radians(*rotation) + 2.0 * PI * i as f64 / *blades as f64;
+                    (angle.cos(), angle.sin())
+                };
+                let (a, b) = (corner(edge), corner(edge + 1));
+                let r = util::random_double().sqrt();
+                let t = util::random_double();
+                (
+                    r * ((1.0 - t) * a.0 + t * b.0),
+                    r * ((1.0 - t) * a.1 + t * b.1),
+                )
+            }
+            Aperture::Image(image) => {
+                let ((u, v), _) = image
+                    .distribution
+                    .sample(util::random_double(), util::random_double());
+                (2.0 * u - 1.0, 1.0 - 2.0 * v)
+            }
+        }
+    }
+
+    /// Fraction of the light passing the point `(x, y)`.
+    pub fn transmission(&self, x: f64, y: f64) -> f64 {
+        match self {
+            Aperture::Circle => (x * x + y * y <= 1.0) as u8 as f64,
+            Aperture::Polygon { blades, rotation } => {
+                // Distance along the middle of the edge facing the point
+                let sector = 2.0 * PI / *blades as f64;
+                let angle = (y.atan2(x) - util::degrees_to_radians(*rotation)).rem_euclid(sector);
+                let r = (x * x + y * y).sqrt();
+                (r * (angle - sector / 2.0).cos() <= (sector / 2.0).cos()) as u8 as f64
+            }
+            Aperture::Image(image) => {
+                if x.abs() > 1.0 || y.abs() > 1.0 {
+                    return 0.0;
+                }
+                let col = (((x + 1.0) / 2.0 * image.width as f64) as usize).min(image.width - 1);
+                let row = (((1.0 - y) / 2.0 * image.height as f64) as usize).min(image.height - 1);
+                image.transmission[row * image.width + col]
+            }
+        }
+    }
+}
+
+#[test]
+fn aperture_test() {
+    use crate::vector::Color;
+
+    let hexagon = Aperture::Polygon {
+        blades: 6,
+        rotation: 0.0,
+    };
+    assert_eq!(1.0, hexagon.transmission(0.99, 0.0));
+    assert_eq!(0.0, hexagon.transmission(0.0, 0.99));
+    assert_eq!(1.0, hexagon.transmission(0.0, 0.86));
+    for _ in 0..100 {
+        let (x, y) = hexagon.sample();
+        assert_eq!(1.0, hexagon.transmission(x, y));
+    }
+
+    // Only the lit right half of the mask is sampled
+    let mask = HdrImage {
+        width: 2,
+        height: 1,
+        pixels: vec![Color::new(0.0, 0.0, 0.0), Color::new(2.0, 2.0, 2.0)],
+    };
+    let black = HdrImage {
+        width: 2,
+        height: 1,
+        pixels: vec![Color::default(); 2],
+    };
+    assert!(ApertureImage::new(&black).is_err());
+    let mask = Aperture::Image(ApertureImage::new(&mask).unwrap());
+    assert_eq!(1.0, mask.transmission(0.5, 0.5));
+    assert_eq!(0.0, mask.transmission(-0.5, 0.5));
+    for _ in 0..100 {
+        assert!(mask.sample().0 >= 0.0);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::sky;
+use crate::util::PI;
+use crate::vector::Color;
+
+static PLANCK: f64 = 6.626_070_15e-34;
+static SPEED_OF_LIGHT: f64 = 2.997_924_58e8;
+static BOLTZMANN: f64 = 1.380_649e-23;
+static STEFAN_BOLTZMANN: f64 = 5.670_374_419e-8;
+/// Lumens per watt of light at 555 nm, where the eye is most sensitive.
+static MAX_LUMINOUS_EFFICACY: f64 = 683.0;
+/// Visible wavelengths integrated over, in nanometres.
+static WAVELENGTH_RANGE: (usize, usize) = (360, 830);
+
+/// Light given off by a black body at some temperature.
+pub struct Blackbody {
+    /// Colour in linear sRGB, scaled to a luminance of 1.
+    pub color: Color,
+    /// Lumens for each watt radiated, over all wavelengths.
+    pub luminous_efficacy: f64,
+}
+
+impl Blackbody {
+    /// Integrates Planck's law against the CIE 1931 colour matching
+    /// functions for a temperature in kelvin.
+    pub fn new(temperature: f64) -> Blackbody {
+        let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+        for wavelength in WAVELENGTH_RANGE.0..=WAVELENGTH_RANGE.1 {
+            let wavelength = wavelength as f64;
+            // Steps of one nanometre
+            let radiance = planck(wavelength, temperature) * 1e-9;
+            let (x_bar, y_bar, z_bar) = color_matching(wavelength);
+            x += x_bar * radiance;
+            y += y_bar * radiance;
+            z += z_bar * radiance;
+        }
+        let total_radiance = STEFAN_BOLTZMANN * temperature.powi(4) / PI;
+
+        Blackbody {
+            color: sky::xyy_to_rgb(x / (x + y + z), y / (x + y + z), 1.0),
+            luminous_efficacy: MAX_LUMINOUS_EFFICACY * y / total_radiance,
+        }
+    }
+
+    /// Radiance of a uniformly glowing surface of `area` square metres
+    /// that gives off `lumens` from its front side.
+    pub fn radiance_for_lumens(&self, lumens: f64, area: f64) -> Color {
+        let luminance = lumens / (PI * area);
+        luminance * sky::LUMINANCE_SCALE * self.color
+    }
+
+    /// Radiance of a surface of `area` square metres radiating `watts` over
+    /// all wavelengths, most of it as invisible infrared at lamp
+    /// temperatures.
+    pub fn radiance_for_watts(&self, watts: f64, area: f64) -> Color {
+        self.radiance_for_lumens(watts * self.luminous_efficacy, area)
+    }
+}
+
+/// Spectral radiance in W / (m² sr m) at a wavelength in nanometres.
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    let lambda = wavelength * 1e-9;
+    2.0 * PLANCK * SPEED_OF_LIGHT * SPEED_OF_LIGHT
+        / (lambda.powi(5)
+            * ((PLANCK * SPEED_OF_LIGHT / (lambda * BOLTZMANN * temperature)).exp() - 1.0))
+}
+
+/// CIE 1931 2° colour matching functions at a wavelength in nanometres,
+/// using the multi-lobe fit of Wyman, Sloan and Shirley.
+fn color_matching(wavelength: f64) -> (f64, f64, f64) {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) * if wavelength < mean { below } else { above };
+        (-0.5 * t * t).exp()
+    };
+    let x = 1.056 * lobe(599.8, 0.0264, 0.0323) + 0.362 * lobe(442.0, 0.0624, 0.0374)
+        - 0.065 * lobe(501.1, 0.0490, 0.0382);
+    let y = 0.821 * lobe(568.8, 0.0213, 0.0247) + 0.286 * lobe(530.9, 0.0613, 0.0322);
+    let z = 1.217 * lobe(437.0, 0.0845, 0.0278) + 0.681 * lobe(459.0, 0.0385, 0.0725);
+    (x, y, z)
+}
+
+#[test]
+fn blackbody_test() {
+    use crate::vector::luminance;
+
+    let warm = Blackbody::new(2700.0);
+    let daylight = Blackbody::new(6500.0);
+
+    assert!((luminance(warm.color) - 1.0).abs() < 1e-3);
+    assert!(warm.color.x > warm.color.y && warm.color.y > warm.color.z);
+    // 6500 K is close to the white point of sRGB
+    assert!(daylight.color.z / daylight.color.x > 0.9 && daylight.color.z / daylight.color.x < 1.1);
+
+    // An incandescent bulb turns only a small part of its power into light,
+    // efficacy peaks at about 95 lm/W near 6600 K
+    assert!(warm.luminous_efficacy > 10.0 && warm.luminous_efficacy < 20.0);
+    let peak = Blackbody::new(6600.0).luminous_efficacy;
+    assert!(peak > 90.0 && peak < 100.0, "{}", peak);
+}
@@ -0,0 +1,81 @@
+use crate::vector::{Point3, Vec3};
+
+/// Axis aligned box.
+#[derive(Clone, Copy)]
+pub struct Bounds {
+    pub min: Point3,
+    pub max: Point3,
+}
+
+impl Bounds {
+    /// Smallest box around all of `points`.
+    pub fn from_points(points: &[Point3]) -> Bounds {
+        points[1..].iter().fold(
+            Bounds {
+                min: points[0],
+                max: points[0],
+            },
+            |bounds, point| {
+                bounds.union(Bounds {
+                    min: *point,
+                    max: *point,
+                })
+            },
+        )
+    }
+
+    pub fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            min: Point3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.diagonal();
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    pub fn contains(&self, p: Point3) -> bool {
+        (self.min.x..=self.max.x).contains(&p.x)
+            && (self.min.y..=self.max.y).contains(&p.y)
+            && (self.min.z..=self.max.z).contains(&p.z)
+    }
+
+    /// Index of the axis along which the box is widest, 0 for X.
+    pub fn largest_axis(&self) -> usize {
+        let diagonal = self.diagonal();
+        if diagonal.x >= diagonal.y && diagonal.x >= diagonal.z {
+            0
+        } else if diagonal.y >= diagonal.z {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+/// Component of `v` along `axis`, 0 for X.
+pub fn axis(v: Vec3, axis: usize) -> f64 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
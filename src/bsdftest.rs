@@ -0,0 +1,335 @@
+//! Statistical checks that every material is physically plausible and that
+//! its sampling routine agrees with its density. New materials should be
+//! added to `all_materials`, and to `continuous_materials` when they have no
+//! delta lobes.
+
+use crate::material::MaterialEnum;
+use crate::principled::Principled;
+use crate::util::{self, PI};
+use crate::vector::{Color, Vec3};
+
+fn all_materials() -> Vec<(&'static str, MaterialEnum)> {
+    let mut materials = continuous_materials();
+    materials.extend([
+        (
+            "smooth metal",
+            MaterialEnum::metal(Color::new(0.9, 0.9, 0.9), 0.0, 0.0),
+        ),
+        (
+            "dielectric",
+            MaterialEnum::Dielectric {
+                index_of_refraction: 1.5,
+            },
+        ),
+        (
+            "smooth rough dielectric",
+            MaterialEnum::RoughDielectric {
+                index_of_refraction: 1.5,
+                roughness: 0.0,
+                absorption: Color::default(),
+            },
+        ),
+        (
+            "clearcoat principled",
+            MaterialEnum::Principled(Principled {
+                clearcoat: 1.0,
+                ..principled()
+            }),
+        ),
+        (
+            "emissive",
+            MaterialEnum::Emissive {
+                radiance: Color::new(1.0, 1.0, 1.0),
+            },
+        ),
+    ]);
+    materials
+}
+
+fn continuous_materials() -> Vec<(&'static str, MaterialEnum)> {
+    vec![
+        (
+            "lambertian",
+            MaterialEnum::Lambertian {
+                albedo: Color::new(1.0, 1.0, 1.0),
+            },
+        ),
+        (
+            "rough gold",
+            MaterialEnum::metal_preset("gold", 0.5, 0.0).unwrap(),
+        ),
+        (
+            "brushed metal",
+            MaterialEnum::metal(Color::new(0.9, 0.9, 0.9), 0.5, 0.8),
+        ),
+        (
+            "rough dielectric",
+            MaterialEnum::RoughDielectric {
+                index_of_refraction: 1.5,
+                roughness: 0.5,
+                absorption: Color::default(),
+            },
+        ),
+        (
+            "regularized dielectric",
+            MaterialEnum::Dielectric {
+                index_of_refraction: 1.5,
+            }
+            .regularized(0.3),
+        ),
+        ("principled", MaterialEnum::Principled(principled())),
+        (
+            "transmissive principled",
+            MaterialEnum::Principled(Principled {
+                metallic: 0.2,
+                sheen: 0.5,
+                transmission: 0.7,
+                ..principled()
+            }),
+        ),
+    ]
+}
+
+fn principled() -> Principled {
+    Principled {
+        base_color: Color::new(0.8, 0.8, 0.8),
+        metallic: 0.0,
+        roughness: 0.5,
+        specular: 0.5,
+        specular_tint: 0.0,
+        sheen: 0.0,
+        clearcoat: 0.0,
+        transmission: 0.0,
+        ior: 1.5,
+    }
+}
+
+/// Outgoing directions the checks are run for, including grazing ones and
+/// ones from inside the object.
+fn outgoing_directions() -> Vec<Vec3> {
+    [10.0, 45.0, 80.0, 120.0, 170.0]
+        .iter()
+        .map(|degrees: &f64| {
+            let theta = util::degrees_to_radians(*degrees);
+            Vec3::new(theta.sin() * 0.8, theta.sin() * 0.6, theta.cos())
+        })
+        .collect()
+}
+
+fn random_u() -> [f64; 3] {
+    [
+        util::random_double(),
+        util::random_double(),
+        util::random_double(),
+    ]
+}
+
+fn direction(cos_theta: f64, phi: f64) -> Vec3 {
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Fraction of light arriving from all directions that is scattered
+/// towards `wo`, estimated by importance sampling.
+fn albedo(material: &MaterialEnum, wo: Vec3, samples: usize) -> Color {
+    let mut total = Color::default();
+    for _ in 0..samples {
+        if let Some(sample) = material.sample(wo, random_u()) {
+            total = total + sample.weight();
+        }
+    }
+    total / samples as f64
+}
+
+#[test]
+fn white_furnace_test() {
+    util::seed(1);
+    for (name, material) in all_materials() {
+        for wo in outgoing_directions() {
+            let albedo = albedo(&material, wo, 20_000);
+            assert!(
+                albedo.max_component() < 1.01,
+                "{} reflects {:?} of the light towards {:?}",
+                name,
+                albedo,
+                wo
+            );
+        }
+    }
+
+    // Materials without absorption or microfacet shadowing lose nothing
+    let lossless = [
+        MaterialEnum::Lambertian {
+            albedo: Color::new(1.0, 1.0, 1.0),
+        },
+        MaterialEnum::Dielectric {
+            index_of_refraction: 1.5,
+        },
+        MaterialEnum::RoughDielectric {
+            index_of_refraction: 1.5,
+            roughness: 0.0,
+            absorption: Color::default(),
+        },
+    ];
+    for material in lossless.iter() {
+        for wo in outgoing_directions() {
+            let albedo = albedo(material, wo, 1000);
+            assert!((albedo - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn reciprocity_test() {
+    util::seed(2);
+    for (name, material) in all_materials() {
+        for _ in 0..1000 {
+            let wo = Vec3::random_unit_vector();
+            let wi = Vec3::random_unit_vector();
+            // The transmission lobes leave out the radiance scaling by the
+            // squared ratio of indices, so only reflection is symmetric
+            if wo.z * wi.z <= 0.0 {
+                continue;
+            }
+
+            let forward = material.eval(wo, wi);
+            let backward = material.eval(wi, wo);
+            assert!(
+                (forward - backward).length() <= 1e-9 * forward.length().max(1.0),
+                "{} is not reciprocal for {:?} and {:?}: {:?} against {:?}",
+                name,
+                wo,
+                wi,
+                forward,
+                backward
+            );
+        }
+    }
+}
+
+/// Grid over the sphere in spherical coordinates. Cells are smallest at the
+/// poles, where the lobes of a surface seen head on are concentrated.
+static THETA_BINS: usize = 24;
+static PHI_BINS: usize = 48;
+
+fn bin(w: Vec3) -> usize {
+    let theta = w.z.clamp(-1.0, 1.0).acos();
+    let theta_bin = ((theta / PI * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+    let phi = w.y.atan2(w.x).rem_euclid(2.0 * PI);
+    let phi_bin = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+    theta_bin * PHI_BINS + phi_bin
+}
+
+/// Integral of the density over each cell, by the midpoint rule on a finer
+/// grid.
+fn integrate_pdf(material: &MaterialEnum, wo: Vec3) -> Vec<f64> {
+    let resolution = 12;
+    let d_theta = PI / (THETA_BINS * resolution) as f64;
+    let d_phi = 2.0 * PI / (PHI_BINS * resolution) as f64;
+    let mut integrals = vec![0.0; THETA_BINS * PHI_BINS];
+    for i in 0..THETA_BINS * resolution {
+        let theta = (i as f64 + 0.5) * d_theta;
+        for j in 0..PHI_BINS * resolution {
+            let phi = (j as f64 + 0.5) * d_phi;
+            let wi = direction(theta.cos(), phi);
+            let cell = (i / resolution) * PHI_BINS + j / resolution;
+            integrals[cell] += material.pdf(wo, wi) * theta.sin() * d_theta * d_phi;
+        }
+    }
+    integrals
+}
+
+#[test]
+fn pdf_integrates_to_one_test() {
+    util::seed(3);
+    for (name, material) in continuous_materials() {
+        for wo in outgoing_directions() {
+            // Samples can be rejected, e.g. when a reflection off a
+            // microfacet points into the surface, so the density only
+            // integrates to the fraction of samples that succeed
+            let samples = 20_000;
+            let accepted = (0..samples)
+                .filter(|_| material.sample(wo, random_u()).is_some())
+                .count();
+            let expected = accepted as f64 / samples as f64;
+
+            let integral: f64 = integrate_pdf(&material, wo).iter().sum();
+            assert!(
+                (integral - expected).abs() < 0.02,
+                "pdf of {} integrates to {} instead of {} for {:?}",
+                name,
+                integral,
+                expected,
+                wo
+            );
+        }
+    }
+}
+
+/// Critical value of the chi-square distribution with `dof` degrees of
+/// freedom, using the Wilson-Hilferty approximation. `z` is the matching
+/// quantile of the standard normal distribution.
+fn chi_square_critical_value(dof: usize, z: f64) -> f64 {
+    let k = dof as f64;
+    let h = 2.0 / (9.0 * k);
+    k * (1.0 - h + z * h.sqrt()).powi(3)
+}
+
+#[test]
+fn sample_matches_pdf_chi_square_test() {
+    util::seed(4);
+    let samples = 100_000;
+    for (name, material) in continuous_materials() {
+        for wo in outgoing_directions() {
+            let mut observed = vec![0.0; THETA_BINS * PHI_BINS];
+            for _ in 0..samples {
+                if let Some(sample) = material.sample(wo, random_u()) {
+                    observed[bin(sample.wi)] += 1.0;
+                }
+            }
+            let expected: Vec<f64> = integrate_pdf(&material, wo)
+                .iter()
+                .map(|p| p * samples as f64)
+                .collect();
+
+            // Cells expected to receive few samples are pooled so that the
+            // statistic stays approximately chi-square distributed
+            let mut chi_square = 0.0;
+            let mut dof = 0;
+            let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+            for (o, e) in observed.iter().zip(expected.iter()) {
+                if *e < 5.0 {
+                    pooled_observed += o;
+                    pooled_expected += e;
+                } else {
+                    chi_square += (o - e) * (o - e) / e;
+                    dof += 1;
+                }
+            }
+            if pooled_expected >= 5.0 {
+                chi_square += (pooled_observed - pooled_expected).powi(2) / pooled_expected;
+                dof += 1;
+            } else {
+                assert!(
+                    pooled_observed < 5.0 + 5.0 * pooled_expected,
+                    "{} sampled {} directions where the pdf expects {} for {:?}",
+                    name,
+                    pooled_observed,
+                    pooled_expected,
+                    wo
+                );
+            }
+
+            // One in ten thousand chance of failing a correct material
+            let critical = chi_square_critical_value(dof - 1, 3.72);
+            assert!(
+                chi_square < critical,
+                "{} failed the chi-square test for {:?}: {:.1} with {} degrees of freedom",
+                name,
+                wo,
+                chi_square,
+                dof - 1
+            );
+        }
+    }
+}
@@ -1,7 +1,170 @@
+use crate::aperture::Aperture;
+use crate::lens::LensSystem;
 use crate::ray::Ray;
-use crate::util;
+use crate::sky;
+use crate::util::{self, PI};
 use crate::vector::{Point3, Vec3};
 
+/// How directions in the scene are mapped onto the image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Projection {
+    /// Thin lens camera with the field of view and depth of field given to
+    /// `Camera::new`.
+    Perspective,
+    /// Parallel rays through a view `height` world units tall, for
+    /// technical drawings.
+    Orthographic { height: f64 },
+    /// Circular fisheye covering `fov` degrees across the height of the
+    /// image, with everything outside the circle left black.
+    Fisheye { mapping: FisheyeMapping, fov: f64 },
+    /// Full 360° panorama, longitude across and latitude up the image,
+    /// with the horizon kept level. With `stereo` both eyes are rendered
+    /// into one image.
+    Equirectangular { stereo: Option<Stereo> },
+}
+
+/// Distance from the centre of a fisheye image for a ray at angle θ from
+/// the view direction.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FisheyeMapping {
+    /// Proportional to θ, which keeps angles between objects.
+    Equidistant,
+    /// Proportional to sin(θ / 2), which keeps areas.
+    Equisolid,
+}
+
+/// Omni-directional stereo: every column of a panorama is seen from eyes
+/// on a circle, so that looking in any direction gives a stereo pair.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Stereo {
+    /// Distance between the eyes in world units.
+    pub ipd: f64,
+    /// Latitude in degrees from which the eyes move together, meeting at
+    /// the poles so that looking straight up or down is comfortable.
+    pub pole_merge: f64,
+    pub layout: StereoLayout,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Stereo {
+            ipd: 0.064,
+            pole_merge: 60.0,
+            layout: StereoLayout::TopBottom,
+        }
+    }
+}
+
+impl Stereo {
+    /// Which eye the point `(s, t)` of the whole image belongs to, -1 for
+    /// the left and 1 for the right, and the point within that eye's image.
+    fn split(&self, s: f64, t: f64) -> (f64, f64, f64) {
+        match self.layout {
+            StereoLayout::TopBottom if t >= 0.5 => (s, 2.0 * t - 1.0, -1.0),
+            StereoLayout::TopBottom => (s, 2.0 * t, 1.0),
+            StereoLayout::SideBySide if s < 0.5 => (2.0 * s, t, -1.0),
+            StereoLayout::SideBySide => (2.0 * s - 1.0, t, 1.0),
+        }
+    }
+
+    /// Fraction of the eye distance kept at `latitude` radians.
+    fn separation(&self, latitude: f64) -> f64 {
+        let start = util::degrees_to_radians(self.pole_merge);
+        if latitude.abs() <= start || start >= PI / 2.0 {
+            return 1.0;
+        }
+        let x = (latitude.abs() - start) / (PI / 2.0 - start);
+        1.0 - x * x * (3.0 - 2.0 * x)
+    }
+}
+
+/// How the images of the two eyes are put together.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StereoLayout {
+    /// Left eye above the right one.
+    TopBottom,
+    /// Left eye left of the right one.
+    SideBySide,
+}
+
+impl StereoLayout {
+    pub fn parse(name: &str) -> Option<StereoLayout> {
+        match name {
+            "top-bottom" => Some(StereoLayout::TopBottom),
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            _ => None,
+        }
+    }
+}
+
+/// Exposure of a physical camera, which also sets the depth of field.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Exposure {
+    /// Focal length over aperture diameter.
+    pub f_number: f64,
+    /// Seconds the shutter is open.
+    pub shutter: f64,
+    pub iso: f64,
+}
+
+/// The sunny 16 rule, which keeps daylight scenes near their linear
+/// brightness.
+impl Default for Exposure {
+    fn default() -> Self {
+        Exposure {
+            f_number: 16.0,
+            shutter: 0.01,
+            iso: 100.0,
+        }
+    }
+}
+
+impl Exposure {
+    /// Factor from scene radiance to image values, from the saturation
+    /// based exposure value at ISO 100.
+    pub fn scale(&self) -> f64 {
+        let ev100 = (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2();
+        1.0 / (sky::LUMINANCE_SCALE * 1.2 * ev100.exp2())
+    }
+}
+
+/// Diagonal of a full frame 35 mm sensor in metres.
+static FULL_FRAME_DIAGONAL: f64 = 0.04327;
+static DEFAULT_ORTHOGRAPHIC_HEIGHT: f64 = 8.0;
+static DEFAULT_FISHEYE_FOV: f64 = 180.0;
+
+impl Projection {
+    pub fn parse(name: &str) -> Option<Projection> {
+        let fisheye = |mapping| Projection::Fisheye {
+            mapping,
+            fov: DEFAULT_FISHEYE_FOV,
+        };
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic {
+                height: DEFAULT_ORTHOGRAPHIC_HEIGHT,
+            }),
+            "equidistant" => Some(fisheye(FisheyeMapping::Equidistant)),
+            "equisolid" => Some(fisheye(FisheyeMapping::Equisolid)),
+            "equirectangular" => Some(Projection::Equirectangular { stereo: None }),
+            _ => None,
+        }
+    }
+
+    /// Width over height of the image, when the projection needs a
+    /// particular one.
+    pub fn aspect_ratio(&self) -> Option<f64> {
+        match self {
+            Projection::Equirectangular { stereo } => match stereo.map(|stereo| stereo.layout) {
+                None => Some(2.0),
+                Some(StereoLayout::TopBottom) => Some(1.0),
+                Some(StereoLayout::SideBySide) => Some(4.0),
+            },
+            _ => None,
+        }
+    }
+}
+
 pub struct Camera {
     origin: Point3,
     lower_left_corner: Point3,
@@ -10,7 +173,30 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    /// Unit up vector of the scene, the pole of panoramas.
+    up: Vec3,
+    aspect_ratio: f64,
     lens_radius: f64,
+    focus_dist: f64,
+    /// Sets the aperture and the brightness when given, otherwise the lens
+    /// radius is fixed in world units and radiance is kept as it is.
+    exposure: Option<Exposure>,
+    /// Sensor diagonal in metres, which with the field of view gives the
+    /// focal length and so the aperture size for an f-number.
+    sensor_diagonal: f64,
+    /// Offset of the image on the film, in image widths and heights.
+    shift: (f64, f64),
+    /// Normal of the plane of focus.
+    focus_normal: Vec3,
+    aperture: Aperture,
+    /// How far the lens barrel moves across the aperture towards the edges
+    /// of the image, in aperture radii at the top edge. Clips out of focus
+    /// highlights into cat's eyes and darkens the corners.
+    cats_eye: f64,
+    /// Traced instead of the thin lens when set, the field of view then
+    /// comes from its focal length.
+    lens: Option<LensSystem>,
+    projection: Projection,
 }
 
 impl Camera {
@@ -43,20 +229,350 @@ impl Camera {
             lower_left_corner,
             vertical,
             horizontal,
-            w,
             u,
             v,
+            w,
+            up: vup.unit_vector(),
+            aspect_ratio,
             lens_radius,
+            focus_dist,
+            exposure: None,
+            sensor_diagonal: FULL_FRAME_DIAGONAL,
+            shift: (0.0, 0.0),
+            focus_normal: w,
+            aperture: Aperture::Circle,
+            cats_eye: 0.0,
+            lens: None,
+            projection: Projection::Perspective,
         }
     }
 
-    pub fn ray(&self, s: f64, t: f64) -> Ray {
-        let rd: Vec3 = self.lens_radius * Vec3::random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
+    /// Sensor diagonal in millimetres. A larger sensor needs a longer lens
+    /// for the same view, with a wider aperture at the same f-number.
+    pub fn with_sensor(mut self, diagonal: f64) -> Self {
+        self.sensor_diagonal = diagonal * 0.001;
+        if let Some(exposure) = self.exposure {
+            self = self.with_exposure(exposure);
+        }
+        self
+    }
+
+    /// Exposes like a physical camera, scaling the radiance and setting
+    /// the aperture from the f-number.
+    pub fn with_exposure(mut self, exposure: Exposure) -> Self {
+        // World units are metres, the aperture grows with the focal length
+        let sensor_height =
+            self.sensor_diagonal / (1.0 + self.aspect_ratio * self.aspect_ratio).sqrt();
+        let h = self.vertical.length() / (2.0 * self.focus_dist);
+        let focal_length = 0.5 * sensor_height / h;
+        self.lens_radius = focal_length / (2.0 * exposure.f_number);
+        self.exposure = Some(exposure);
+        self
+    }
+
+    /// Moves the lens parallel to the film by fractions of the image width
+    /// and height, framing higher or lower without tilting the view, so
+    /// that verticals stay straight.
+    pub fn with_shift(mut self, x: f64, y: f64) -> Self {
+        self.shift = (x, y);
+        self
+    }
+
+    /// Tilts the plane of focus by `degrees` about the horizontal axis
+    /// through the focus point, bringing it closer at the bottom of the
+    /// image for positive angles, as with a tilted lens.
+    pub fn with_tilt(mut self, degrees: f64) -> Self {
+        let tilt = util::degrees_to_radians(degrees);
+        self.focus_normal = tilt.cos() * self.w + tilt.sin() * self.v.unit_vector();
+        self
+    }
+
+    /// Factor applied to the radiance of every sample.
+    pub fn exposure_scale(&self) -> f64 {
+        self.exposure.map_or(1.0, |exposure| exposure.scale())
+    }
+
+    pub fn with_aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_cats_eye(mut self, cats_eye: f64) -> Self {
+        self.cats_eye = cats_eye;
+        self
+    }
+
+    /// Traces perspective rays through `lens`, focused at the focus
+    /// distance.
+    pub fn with_lens(mut self, mut lens: LensSystem) -> Self {
+        lens.focus(self.focus_dist);
+        self.lens = Some(lens);
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Ray through the point `(s, t)` of the image, both from 0 to 1 with
+    /// `t` counted from the bottom, and the weight of its sample. `None`
+    /// outside the image circle of a fisheye and for light blocked inside
+    /// the lens. Only a lens system gives weights other than 1.
+    pub fn ray(&self, s: f64, t: f64) -> Option<(Ray, f64)> {
+        match self.projection {
+            Projection::Perspective => {
+                let (s, t) = (s + self.shift.0, t + self.shift.1);
+                if let Some(lens) = &self.lens {
+                    return self.lens_ray(lens, s, t);
+                }
+                let (x, y) = self.aperture.sample();
+                if self.cats_eye > 0.0 {
+                    let barrel_x = self.cats_eye * (2.0 * s - 1.0) * self.aspect_ratio;
+                    let barrel_y = self.cats_eye * (2.0 * t - 1.0);
+                    if (x - barrel_x).powi(2) + (y - barrel_y).powi(2) > 1.0 {
+                        return None;
+                    }
+                }
+                let offset = self.lens_radius * (self.u * x + self.v * y);
+
+                // Sharp where the ray through the lens centre meets the plane of focus
+                let direction =
+                    self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin;
+                let along = direction.dot(self.focus_normal);
+                if along >= 0.0 {
+                    // Parallel to a tilted plane of focus, sharp at infinity
+                    return Some((Ray::new(self.origin + offset, direction), 1.0));
+                }
+                let distance = -self.focus_dist * self.w.dot(self.focus_normal) / along;
+                Some((
+                    Ray::new(self.origin + offset, distance * direction - offset),
+                    1.0,
+                ))
+            }
+            Projection::Orthographic { height } => {
+                let offset = (s - 0.5) * self.aspect_ratio * height * self.u.unit_vector()
+                    + (t - 0.5) * height * self.v.unit_vector();
+                Some((Ray::new(self.origin + offset, -self.w), 1.0))
+            }
+            Projection::Fisheye { mapping, fov } => {
+                // Unit radius at the top and bottom edges of the image
+                let x = (2.0 * s - 1.0) * self.aspect_ratio;
+                let y = 2.0 * t - 1.0;
+                let r = (x * x + y * y).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let half_fov = util::degrees_to_radians(fov) / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (half_fov / 2.0).sin()).asin(),
+                };
+                let phi = y.atan2(x);
+                let direction = theta.sin()
+                    * (phi.cos() * self.u.unit_vector() + phi.sin() * self.v.unit_vector())
+                    - theta.cos() * self.w;
+                Some((Ray::new(self.origin, direction), 1.0))
+            }
+            Projection::Equirectangular { stereo } => {
+                let (s, t, eye) = stereo.map_or((s, t, 0.0), |stereo| stereo.split(s, t));
+                let longitude = (s - 0.5) * 2.0 * PI;
+                let latitude = (t - 0.5) * PI;
+                let (forward, right) = self.level_frame();
+                let direction = latitude.cos()
+                    * (longitude.cos() * forward + longitude.sin() * right)
+                    + latitude.sin() * self.up;
+                // The eyes sit on a circle, at right angles to the direction
+                let origin = match stereo {
+                    Some(stereo) => {
+                        let side = longitude.cos() * right - longitude.sin() * forward;
+                        self.origin + eye * 0.5 * stereo.ipd * stereo.separation(latitude) * side
+                    }
+                    None => self.origin,
+                };
+                Some((Ray::new(origin, direction), 1.0))
+            }
+        }
+    }
+
+    /// Ray through a lens system. The lens turns the image upside down, so
+    /// the top of the image is at the bottom of the film.
+    fn lens_ray(&self, lens: &LensSystem, s: f64, t: f64) -> Option<(Ray, f64)> {
+        let (width, height) = lens.film_size(self.aspect_ratio);
+        let (origin, direction, weight) =
+            lens.ray(-(s - 0.5) * width, -(t - 0.5) * height, &self.aperture)?;
+        let (u, v) = (self.u.unit_vector(), self.v.unit_vector());
+        Some((
+            Ray::new(
+                self.origin + origin.x * u + origin.y * v + origin.z * self.w,
+                direction.x * u + direction.y * v + direction.z * self.w,
+            ),
+            weight,
+        ))
+    }
+
+    /// View direction and right hand side turned about the up vector only,
+    /// so that panoramas have a level horizon.
+    fn level_frame(&self) -> (Vec3, Vec3) {
+        let forward = (-self.w - self.up * -self.w.dot(self.up)).unit_vector();
+        (forward, forward.cross(self.up))
+    }
+
+    /// Jitters a sample inside pixel `(col, row)`, with rows counted from the
+    /// bottom of the image. Returns the sample position in pixels from the
+    /// top left corner, as used by the film, and the ray through it with its
+    /// weight.
+    pub fn sample_pixel(
+        &self,
+        col: i32,
+        row: i32,
+        image_width: i32,
+        image_height: i32,
+    ) -> (f64, f64, Option<(Ray, f64)>) {
+        let x = col as f64 + util::random_double();
+        let y = row as f64 + util::random_double();
+        let u = x / (image_width as f64 - 1.0);
+        let v = y / (image_height as f64 - 1.0);
+        (x, image_height as f64 - y, self.ray(u, v))
+    }
+}
+
+#[test]
+fn projection_test() {
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        90.0,
+        2.0,
+        0.0,
+        1.0,
+    );
+    let direction = |camera: &Camera, s, t| camera.ray(s, t).unwrap().0.dir.unit_vector();
+    let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-9;
+
+    let orthographic = camera.with_projection(Projection::Orthographic { height: 2.0 });
+    let corner = orthographic.ray(1.0, 1.0).unwrap().0;
+    assert!(close(Point3::new(2.0, 1.0, 0.0), corner.orig));
+    assert!(close(Vec3::new(0.0, 0.0, -1.0), corner.dir));
+
+    // The top of a 180° fisheye looks straight up, corners are outside
+    let fisheye = orthographic.with_projection(Projection::Fisheye {
+        mapping: FisheyeMapping::Equisolid,
+        fov: 180.0,
+    });
+    assert!(close(
+        Vec3::new(0.0, 1.0, 0.0),
+        direction(&fisheye, 0.5, 1.0)
+    ));
+    assert!(close(
+        Vec3::new(0.0, 0.0, -1.0),
+        direction(&fisheye, 0.5, 0.5)
+    ));
+    assert!(fisheye.ray(1.0, 1.0).is_none());
+
+    let panorama = fisheye.with_projection(Projection::Equirectangular { stereo: None });
+    assert!(close(
+        Vec3::new(0.0, 0.0, -1.0),
+        direction(&panorama, 0.5, 0.5)
+    ));
+    assert!(close(
+        Vec3::new(1.0, 0.0, 0.0),
+        direction(&panorama, 0.75, 0.5)
+    ));
+    assert!(close(
+        Vec3::new(0.0, 0.0, 1.0),
+        direction(&panorama, 0.0, 0.5)
+    ));
+    assert!(close(
+        Vec3::new(0.0, 1.0, 0.0),
+        direction(&panorama, 0.3, 1.0)
+    ));
+}
+
+#[test]
+fn omni_stereo_test() {
+    let stereo = Stereo {
+        ipd: 0.1,
+        pole_merge: 45.0,
+        layout: StereoLayout::SideBySide,
+    };
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        90.0,
+        4.0,
+        0.0,
+        1.0,
+    )
+    .with_projection(Projection::Equirectangular {
+        stereo: Some(stereo),
+    });
+    let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-9;
+
+    // Looking ahead the eyes are side by side, looking right one is behind the other
+    let left = camera.ray(0.25, 0.5).unwrap().0;
+    let right = camera.ray(0.75, 0.5).unwrap().0;
+    assert!(close(Point3::new(-0.05, 0.0, 0.0), left.orig));
+    assert!(close(Point3::new(0.05, 0.0, 0.0), right.orig));
+    assert!(close(left.dir, right.dir));
+    let right = camera.ray(0.875, 0.5).unwrap().0;
+    assert!(close(Point3::new(0.0, 0.0, 0.05), right.orig));
+
+    // The eyes meet at the poles
+    assert!(close(
+        Point3::new(0.0, 0.0, 0.0),
+        camera.ray(0.25, 1.0).unwrap().0.orig
+    ));
+    assert_eq!(1.0, stereo.separation(PI / 4.0));
+}
+
+#[test]
+fn tilt_shift_test() {
+    // The sunny 16 rule keeps the brightness, a stop more light doubles it
+    assert!((Exposure::default().scale() - 1.0).abs() < 0.03);
+    let longer = Exposure {
+        shutter: 0.02,
+        ..Exposure::default()
+    };
+    assert!((longer.scale() / Exposure::default().scale() - 2.0).abs() < 1e-9);
+
+    let camera = |f_number| {
+        Camera::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            90.0,
+            1.0,
+            0.1,
+            1.0,
+        )
+        .with_exposure(Exposure {
+            f_number,
+            ..Exposure::default()
+        })
+    };
+    let close = |a: Vec3, b: Vec3| (a - b).length() < 1e-9;
+
+    // Shifting up looks higher with the view direction unchanged
+    let shifted = camera(util::INFINITY).with_shift(0.0, 0.5);
+    let ray = shifted.ray(0.5, 0.5).unwrap().0;
+    assert!(close(
+        Vec3::new(0.0, 1.0, -1.0).unit_vector(),
+        ray.dir.unit_vector()
+    ));
+    assert!(close(
+        Vec3::new(0.0, 0.0, -1.0),
+        shifted.ray(0.5, 0.0).unwrap().0.dir.unit_vector()
+    ));
 
-        return Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        );
+    // Tilted 45°, the plane of focus comes to half the distance at the
+    // bottom edge of the view
+    let tilted = camera(1.0).with_tilt(45.0);
+    let sharp = Point3::new(0.0, -0.5, -0.5);
+    for _ in 0..10 {
+        let ray = tilted.ray(0.5, 0.0).unwrap().0;
+        assert!((sharp - ray.orig).cross(ray.dir).length() < 1e-9);
     }
 }
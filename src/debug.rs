@@ -0,0 +1,145 @@
+use crate::framebuffer::Framebuffer;
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::{ray_color, PathSettings, Ray};
+use crate::stats::{self, Counter};
+use crate::util;
+use crate::vector::Color;
+use crate::world::Scene;
+
+/// Visualisations that replace the path traced colour of each sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugMode {
+    /// World space normal of the first hit, mapped from -1..1 to 0..1.
+    Normals,
+    /// Green where the camera sees the outside of a surface, red for the inside.
+    Facing,
+    Distance,
+    Material,
+    Uv,
+    /// Intersection tests for the whole path.
+    Cost,
+    Bounces,
+}
+
+impl DebugMode {
+    pub fn parse(name: &str) -> Option<DebugMode> {
+        match name {
+            "normals" => Some(DebugMode::Normals),
+            "facing" => Some(DebugMode::Facing),
+            "distance" => Some(DebugMode::Distance),
+            "material" => Some(DebugMode::Material),
+            "uv" => Some(DebugMode::Uv),
+            "cost" => Some(DebugMode::Cost),
+            "bounces" => Some(DebugMode::Bounces),
+            _ => None,
+        }
+    }
+
+    /// Scalar modes are normalised by the largest value in the image and
+    /// shown as a heatmap once rendering has finished.
+    pub fn is_heatmap(&self) -> bool {
+        matches!(
+            self,
+            DebugMode::Distance | DebugMode::Cost | DebugMode::Bounces
+        )
+    }
+
+    pub fn shade(&self, ray: Ray, scene: &Scene, settings: &PathSettings) -> Color {
+        match self {
+            DebugMode::Cost => {
+                let before = path_cost();
+                ray_color(ray, scene, settings);
+                scalar((path_cost() - before) as f64)
+            }
+            DebugMode::Bounces => scalar(ray_color(ray, scene, settings).bounces as f64),
+            _ => {
+                let mut hit_record = HitRecord::default();
+                if !scene
+                    .world
+                    .hit(&ray, 0.001, util::INFINITY, &mut hit_record)
+                {
+                    return Color::default();
+                }
+                self.shade_hit(&ray, &hit_record)
+            }
+        }
+    }
+
+    fn shade_hit(&self, ray: &Ray, hit_record: &HitRecord) -> Color {
+        match self {
+            DebugMode::Normals => 0.5 * (hit_record.normal + Color::new(1.0, 1.0, 1.0)),
+            DebugMode::Facing => {
+                if hit_record.is_front_face {
+                    Color::new(0.0, 1.0, 0.0)
+                } else {
+                    Color::new(1.0, 0.0, 0.0)
+                }
+            }
+            DebugMode::Distance => scalar(hit_record.t * ray.dir.length()),
+            DebugMode::Material => palette(hit_record.material.kind()),
+            DebugMode::Uv => Color::new(hit_record.u, hit_record.v, 0.0),
+            DebugMode::Cost | DebugMode::Bounces => Color::default(),
+        }
+    }
+}
+
+fn path_cost() -> u64 {
+    stats::get(Counter::IntersectionTests)
+}
+
+fn scalar(x: f64) -> Color {
+    Color::new(x, x, x)
+}
+
+/// Distinct colour for each index, spreading hues by the golden ratio.
+fn palette(index: usize) -> Color {
+    let hue = (index as f64 * 0.618_033_988_75 + 0.1).fract();
+    let channel = |offset: f64| {
+        let h = (hue + offset).fract() * 6.0;
+        util::clamp((h - 3.0).abs() - 1.0, 0.0, 1.0)
+    };
+    Color::new(channel(0.0), channel(2.0 / 3.0), channel(1.0 / 3.0))
+}
+
+/// Normalises a scalar image by its largest value and maps it from blue
+/// through green to red. Pixels with a value of zero, e.g. rays that missed
+/// everything, stay black.
+pub fn apply_heatmap(framebuffer: &mut Framebuffer) {
+    let max = framebuffer
+        .pixels
+        .iter()
+        .map(|pixel| pixel.x)
+        .fold(0.0, f64::max);
+    if max <= 0.0 {
+        return;
+    }
+
+    for pixel in framebuffer.pixels.iter_mut() {
+        if pixel.x > 0.0 {
+            *pixel = heat(pixel.x / max);
+        }
+    }
+}
+
+fn heat(t: f64) -> Color {
+    let t = util::clamp(t, 0.0, 1.0);
+    if t < 0.5 {
+        let s = t * 2.0;
+        Color::new(0.0, s, 1.0 - s)
+    } else {
+        let s = (t - 0.5) * 2.0;
+        Color::new(s, 1.0 - s, 0.0)
+    }
+}
+
+#[test]
+fn heatmap_normalises_to_max_test() {
+    let mut framebuffer = Framebuffer::new(2, 1);
+    framebuffer.pixels[0] = scalar(2.0);
+    framebuffer.pixels[1] = scalar(4.0);
+
+    apply_heatmap(&mut framebuffer);
+
+    assert_eq!(Color::new(0.0, 1.0, 0.0), framebuffer.pixels[0]);
+    assert_eq!(Color::new(1.0, 0.0, 0.0), framebuffer.pixels[1]);
+}
@@ -0,0 +1,181 @@
+use crate::framebuffer::Framebuffer;
+use crate::vector::Color;
+
+/// Parameters of the joint bilateral denoiser. Each sigma controls how quickly
+/// the weight of a neighbour falls off with distance in that feature.
+#[derive(Clone, Copy)]
+pub struct DenoiseSettings {
+    /// Half width of the filter window in pixels.
+    pub radius: usize,
+    pub sigma_spatial: f64,
+    pub sigma_color: f64,
+    pub sigma_albedo: f64,
+    pub sigma_normal: f64,
+}
+
+impl Default for DenoiseSettings {
+    fn default() -> Self {
+        DenoiseSettings {
+            radius: 5,
+            sigma_spatial: 3.0,
+            sigma_color: 0.6,
+            sigma_albedo: 0.1,
+            sigma_normal: 0.3,
+        }
+    }
+}
+
+/// Joint bilateral filter guided by first-hit albedo and normal buffers.
+///
+/// The colour is divided by the albedo before filtering so that only the
+/// lighting is smoothed, then multiplied back to keep texture detail sharp.
+/// Neighbours are weighted by their distance in screen space, illumination,
+/// albedo and normal, which keeps edges between objects intact.
+pub fn denoise(
+    color: &Framebuffer,
+    albedo: &Framebuffer,
+    normal: &Framebuffer,
+    settings: &DenoiseSettings,
+) -> Framebuffer {
+    let width = color.width;
+    let height = color.height;
+
+    let illumination: Vec<Color> = color
+        .pixels
+        .iter()
+        .zip(albedo.pixels.iter())
+        .map(|(c, a)| demodulate(*c, *a))
+        .collect();
+
+    let spatial = -0.5 / (settings.sigma_spatial * settings.sigma_spatial);
+    let color_falloff = -0.5 / (settings.sigma_color * settings.sigma_color);
+    let albedo_falloff = -0.5 / (settings.sigma_albedo * settings.sigma_albedo);
+    let normal_falloff = -0.5 / (settings.sigma_normal * settings.sigma_normal);
+    let radius = settings.radius as isize;
+
+    let mut output = Framebuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let center = y * width + x;
+            let center_illumination = tone_compress(illumination[center]);
+            let center_albedo = albedo.pixels[center];
+            let center_normal = normal.pixels[center];
+
+            let mut sum = Color::default();
+            let mut weight_sum = 0.0;
+            for dy in -radius..=radius {
+                let ny = y as isize + dy;
+                if ny < 0 || ny >= height as isize {
+                    continue;
+                }
+                for dx in -radius..=radius {
+                    let nx = x as isize + dx;
+                    if nx < 0 || nx >= width as isize {
+                        continue;
+                    }
+
+                    let neighbour = ny as usize * width + nx as usize;
+                    let distance = (dx * dx + dy * dy) as f64;
+                    let color_distance = (tone_compress(illumination[neighbour])
+                        - center_illumination)
+                        .length_squared();
+                    let albedo_distance =
+                        (albedo.pixels[neighbour] - center_albedo).length_squared();
+                    let normal_distance =
+                        (normal.pixels[neighbour] - center_normal).length_squared();
+
+                    let weight = f64::exp(
+                        spatial * distance
+                            + color_falloff * color_distance
+                            + albedo_falloff * albedo_distance
+                            + normal_falloff * normal_distance,
+                    );
+                    sum = sum + weight * illumination[neighbour];
+                    weight_sum += weight;
+                }
+            }
+
+            output.pixels[center] = remodulate(sum / weight_sum, center_albedo);
+        }
+    }
+
+    output
+}
+
+static MIN_ALBEDO: f64 = 0.01;
+
+fn demodulate(color: Color, albedo: Color) -> Color {
+    Color::new(
+        color.x / albedo.x.max(MIN_ALBEDO),
+        color.y / albedo.y.max(MIN_ALBEDO),
+        color.z / albedo.z.max(MIN_ALBEDO),
+    )
+}
+
+fn remodulate(illumination: Color, albedo: Color) -> Color {
+    Color::new(
+        illumination.x * albedo.x.max(MIN_ALBEDO),
+        illumination.y * albedo.y.max(MIN_ALBEDO),
+        illumination.z * albedo.z.max(MIN_ALBEDO),
+    )
+}
+
+/// Compresses bright values so fireflies do not dominate the colour distance.
+fn tone_compress(color: Color) -> Color {
+    Color::new(
+        color.x / (1.0 + color.x),
+        color.y / (1.0 + color.y),
+        color.z / (1.0 + color.z),
+    )
+}
+
+#[test]
+fn denoise_keeps_feature_edges_test() {
+    use crate::util;
+    use crate::vector::Vec3;
+
+    // Noisy lighting over two objects that meet in the middle of the image
+    util::seed(5);
+    let size = 16;
+    let mut color = Framebuffer::new(size, size);
+    let mut albedo = Framebuffer::new(size, size);
+    let mut normal = Framebuffer::new(size, size);
+    let expected = |x: usize| if x < size / 2 { 0.2 * 1.0 } else { 0.8 * 0.5 };
+    for y in 0..size {
+        for x in 0..size {
+            let (grey, lighting, facing) = if x < size / 2 {
+                (0.2, 1.0, Vec3::new(0.0, 0.0, 1.0))
+            } else {
+                (0.8, 0.5, Vec3::new(1.0, 0.0, 0.0))
+            };
+            let noisy = lighting * util::random_double_rng(0.6, 1.4);
+            let i = y * size + x;
+            albedo.pixels[i] = Color::new(grey, grey, grey);
+            normal.pixels[i] = facing;
+            color.pixels[i] = grey * noisy * Color::new(1.0, 1.0, 1.0);
+        }
+    }
+
+    let output = denoise(&color, &albedo, &normal, &DenoiseSettings::default());
+
+    let error = |image: &Framebuffer, columns: std::ops::Range<usize>| {
+        let mut sum = 0.0;
+        for y in 0..size {
+            for x in columns.clone() {
+                sum += (image.pixels[y * size + x].x - expected(x)).powi(2);
+            }
+        }
+        sum / (size * columns.len()) as f64
+    };
+    for half in [0..size / 2, size / 2..size] {
+        assert!(error(&output, half.clone()) < 0.25 * error(&color, half));
+    }
+
+    // The columns either side of the edge keep their own brightness
+    for y in 0..size {
+        for x in [size / 2 - 1, size / 2] {
+            let value = output.pixels[y * size + x].x;
+            assert!((value - expected(x)).abs() < 0.2 * expected(x), "{}", value);
+        }
+    }
+}
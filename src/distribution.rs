@@ -0,0 +1,138 @@
+/// Piecewise constant distribution over `[0, 1)` proportional to a list of
+/// non-negative values.
+pub struct Distribution1D {
+    function: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(function: &[f64]) -> Distribution1D {
+        let n = function.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + function[i].abs() / n as f64;
+        }
+        let integral = cdf[n];
+        if integral == 0.0 {
+            // Nothing to prefer, fall back to uniform sampling
+            for (i, value) in cdf.iter_mut().enumerate() {
+                *value = i as f64 / n as f64;
+            }
+        } else {
+            cdf.iter_mut().for_each(|value| *value /= integral);
+        }
+
+        Distribution1D {
+            function: function.iter().map(|value| value.abs()).collect(),
+            cdf,
+            integral,
+        }
+    }
+
+    fn count(&self) -> usize {
+        self.function.len()
+    }
+
+    /// Maps `u` to a point in `[0, 1)`, returning it with its density and
+    /// the index of the segment it falls into.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // Last segment whose cdf is not above `u`
+        let index = self
+            .cdf
+            .partition_point(|value| *value <= u)
+            .clamp(1, self.count())
+            - 1;
+
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+        let x = ((index as f64 + offset) / self.count() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf_at(index), index)
+    }
+
+    /// Picks one of the values with probability proportional to it,
+    /// returning its index and that probability.
+    pub fn sample_discrete(&self, u: f64) -> (usize, f64) {
+        let (_, _, index) = self.sample(u);
+        (index, self.pmf(index))
+    }
+
+    pub fn pmf(&self, index: usize) -> f64 {
+        self.pdf_at(index) / self.count() as f64
+    }
+
+    fn pdf_at(&self, index: usize) -> f64 {
+        if self.integral == 0.0 {
+            1.0
+        } else {
+            self.function[index] / self.integral
+        }
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let index = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_at(index)
+    }
+}
+
+/// Piecewise constant distribution over the unit square, for a function
+/// given as `width * height` values with rows of constant `v` first.
+pub struct Distribution2D {
+    conditionals: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(function: &[f64], width: usize, height: usize) -> Distribution2D {
+        let conditionals: Vec<Distribution1D> = function
+            .chunks(width)
+            .take(height)
+            .map(Distribution1D::new)
+            .collect();
+        let marginal: Vec<f64> = conditionals.iter().map(|c| c.integral).collect();
+        Distribution2D {
+            conditionals,
+            marginal: Distribution1D::new(&marginal),
+        }
+    }
+
+    /// Picks a row from the marginal and then a column within it. Returns
+    /// the point `(u, v)` and its density over the unit square.
+    pub fn sample(&self, u0: f64, u1: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u1);
+        let (u, pdf_u, _) = self.conditionals[row].sample(u0);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let rows = self.conditionals.len();
+        let row = ((v * rows as f64) as usize).min(rows - 1);
+        self.marginal.pdf(v) * self.conditionals[row].pdf(u)
+    }
+}
+
+#[test]
+fn distribution_2d_test() {
+    let function = [0.0, 1.0, 2.0, 3.0, 0.0, 0.0, 4.0, 6.0];
+    let distribution = Distribution2D::new(&function, 4, 2);
+
+    let mut mass = 0.0;
+    for v in 0..2 {
+        for u in 0..4 {
+            mass += distribution.pdf((u as f64 + 0.5) / 4.0, (v as f64 + 0.5) / 2.0) / 8.0;
+        }
+    }
+    assert!((mass - 1.0).abs() < 1e-12);
+
+    // Zero valued cells are never sampled and the density matches
+    for i in 0..100 {
+        let u0 = i as f64 / 100.0;
+        let ((u, v), pdf) = distribution.sample(u0, 1.0 - u0);
+        assert!(pdf > 0.0);
+        assert!((pdf - distribution.pdf(u, v)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::distribution::Distribution2D;
+use crate::hdr::{self, HdrImage};
+use crate::sky::Sky;
+use crate::util::{self, PI};
+use crate::vector::{luminance, Color, Vec3};
+use std::io;
+
+/// Light arriving from infinitely far away, seen by rays that leave the
+/// scene.
+pub enum Environment {
+    /// Sky gradient from white at the horizon to light blue overhead.
+    Gradient {
+        intensity: f64,
+    },
+    Map(EnvironmentMap),
+    Sky(Box<Sky>),
+}
+
+impl Environment {
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        match self {
+            Environment::Gradient { intensity } => {
+                let unit_direction = direction.unit_vector();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                // Interpolate from white to blue
+                *intensity * ((1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0))
+            }
+            Environment::Map(map) => map.radiance(direction),
+            Environment::Sky(sky) => sky.radiance(direction),
+        }
+    }
+
+    /// Whether `sample` can pick directions towards bright regions. The
+    /// gradient is smooth enough to be found by BSDF sampling alone.
+    pub fn is_sampled(&self) -> bool {
+        !matches!(self, Environment::Gradient { .. })
+    }
+
+    /// Samples a unit direction towards the environment, returning it with
+    /// the radiance arriving from it and its density over solid angle.
+    pub fn sample(&self, u: [f64; 2]) -> Option<(Vec3, Color, f64)> {
+        match self {
+            Environment::Gradient { .. } => None,
+            Environment::Map(map) => map.sample(u),
+            Environment::Sky(sky) => sky.sample(u),
+        }
+    }
+
+    /// Density of `sample` returning `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match self {
+            Environment::Gradient { .. } => 0.0,
+            Environment::Map(map) => map.pdf(direction),
+            Environment::Sky(sky) => sky.pdf(direction),
+        }
+    }
+}
+
+/// Equirectangular environment map. With no rotation the centre of the
+/// image is seen looking down -Z and the top row is straight up.
+pub struct EnvironmentMap {
+    image: HdrImage,
+    /// Rotation about the Y axis, in radians.
+    rotation: f64,
+    intensity: f64,
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Loads a Radiance `.hdr` file, rotated by `rotation` degrees about the
+    /// Y axis and scaled by `intensity`.
+    pub fn load(path: &str, rotation: f64, intensity: f64) -> io::Result<EnvironmentMap> {
+        Ok(EnvironmentMap::new(
+            hdr::load_hdr(path)?,
+            rotation,
+            intensity,
+        ))
+    }
+
+    pub fn new(image: HdrImage, rotation: f64, intensity: f64) -> EnvironmentMap {
+        // Rows near the poles cover less solid angle, weighting by sin(theta)
+        // keeps them from being oversampled
+        let mut function = Vec::with_capacity(image.pixels.len());
+        for y in 0..image.height {
+            let sin_theta = (PI * (y as f64 + 0.5) / image.height as f64).sin();
+            for x in 0..image.width {
+                function.push(luminance(image.pixel(x, y)) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&function, image.width, image.height);
+
+        EnvironmentMap {
+            image,
+            rotation: util::degrees_to_radians(rotation),
+            intensity,
+            distribution,
+        }
+    }
+
+    /// Image coordinates in `[0, 1)` of a world space direction.
+    fn to_uv(&self, direction: Vec3) -> (f64, f64) {
+        let d = direction.unit_vector();
+        let phi = d.x.atan2(-d.z) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = util::clamp(d.y, -1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn lookup(&self, u: f64, v: f64) -> Color {
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        self.intensity * self.image.pixel(x, y)
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let (u, v) = self.to_uv(direction);
+        self.lookup(u, v)
+    }
+
+    pub fn sample(&self, u: [f64; 2]) -> Option<(Vec3, Color, f64)> {
+        let ((map_u, map_v), map_pdf) = self.distribution.sample(u[0], u[1]);
+        let sin_theta = (map_v * PI).sin();
+        if map_pdf == 0.0 || sin_theta == 0.0 {
+            return None;
+        }
+        // Change of variables from the unit square to the sphere
+        let pdf = map_pdf / (2.0 * PI * PI * sin_theta);
+        Some((
+            direction_from_uv(map_u, map_v, self.rotation),
+            self.lookup(map_u, map_v),
+            pdf,
+        ))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        let (u, v) = self.to_uv(direction);
+        let sin_theta = (v * PI).sin();
+        if sin_theta == 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+/// World space direction of the image coordinates `(u, v)` of an
+/// equirectangular map turned by `rotation` radians about the Y axis.
+pub fn direction_from_uv(u: f64, v: f64, rotation: f64) -> Vec3 {
+    let phi = (u - 0.5) * 2.0 * PI + rotation;
+    let theta = v * PI;
+    Vec3::new(
+        theta.sin() * phi.sin(),
+        theta.cos(),
+        -theta.sin() * phi.cos(),
+    )
+}
+
+#[test]
+fn environment_map_sampling_test() {
+    // A dark map with one bright pixel, rotated a quarter turn
+    let (width, height) = (16, 8);
+    let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+    pixels[2 * width + 5] = Color::new(100.0, 50.0, 10.0);
+    let image = HdrImage {
+        width,
+        height,
+        pixels,
+    };
+    let map = EnvironmentMap::new(image, 90.0, 2.0);
+
+    let (direction, radiance, pdf) = map.sample([0.5, 0.5]).unwrap();
+    assert_eq!(Color::new(200.0, 100.0, 20.0), radiance);
+    assert_eq!(radiance, map.radiance(direction));
+    assert!((pdf - map.pdf(direction)).abs() < 1e-9 * pdf);
+
+    // The density integrates to one over the sphere
+    let steps = 400;
+    let mut integral = 0.0;
+    for i in 0..steps {
+        let theta = PI * (i as f64 + 0.5) / steps as f64;
+        for j in 0..steps {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / steps as f64;
+            let w = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            integral += map.pdf(w) * theta.sin();
+        }
+    }
+    integral *= 2.0 * PI * PI / (steps * steps) as f64;
+    assert!((integral - 1.0).abs() < 0.01);
+}
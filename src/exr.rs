@@ -0,0 +1,125 @@
+use std::io::{self, Write};
+
+/// One named channel of 32 bit float samples, stored row by row from the top.
+pub struct Channel {
+    pub name: String,
+    pub samples: Vec<f32>,
+}
+
+static FLOAT_PIXEL_TYPE: i32 = 2;
+static NO_COMPRESSION: u8 = 0;
+static INCREASING_Y: u8 = 0;
+
+/// Writes an uncompressed single-part scanline OpenEXR image. Channel names
+/// follow the usual `layer.channel` convention, e.g. `normal.X`, and are
+/// sorted as the format requires.
+pub fn write_exr(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    channels: &mut [Channel],
+) -> io::Result<()> {
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01]);
+    header.extend_from_slice(&2i32.to_le_bytes());
+
+    let mut channel_list: Vec<u8> = Vec::new();
+    for channel in channels.iter() {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&FLOAT_PIXEL_TYPE.to_le_bytes());
+        // pLinear and three reserved bytes
+        channel_list.extend_from_slice(&[0, 0, 0, 0]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window: Vec<u8> = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+    write_attribute(&mut header, "compression", "compression", &[NO_COMPRESSION]);
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[INCREASING_Y]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    let mut center = 0f32.to_le_bytes().to_vec();
+    center.extend_from_slice(&0f32.to_le_bytes());
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // Each uncompressed block holds one scanline: y, byte count, then the
+    // samples of every channel in turn.
+    let line_bytes = width * channels.len() * 4;
+    let block_size = (4 + 4 + line_bytes) as u64;
+    let table_start = header.len() as u64;
+    let first_block = table_start + 8 * height as u64;
+
+    out.write_all(&header)?;
+    for y in 0..height as u64 {
+        out.write_all(&(first_block + y * block_size).to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_bytes as i32).to_le_bytes())?;
+        for channel in channels.iter() {
+            for sample in &channel.samples[y * width..(y + 1) * width] {
+                out.write_all(&sample.to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+#[test]
+fn exr_layout_test() {
+    let mut channels = vec![
+        Channel {
+            name: String::from("R"),
+            samples: vec![1.0; 4],
+        },
+        Channel {
+            name: String::from("G"),
+            samples: vec![0.5; 4],
+        },
+    ];
+    let mut bytes: Vec<u8> = Vec::new();
+
+    write_exr(&mut bytes, 2, 2, &mut channels).unwrap();
+
+    assert_eq!(&[0x76, 0x2f, 0x31, 0x01], &bytes[0..4]);
+    // Channels are sorted by name
+    assert_eq!("G", channels[0].name);
+    // The first offset points just past the offset table, and the file ends
+    // after two scanlines of two channels with two samples each.
+    let header_end = bytes.len() - 2 * (8 + 2 * 2 * 4) - 2 * 8;
+    let first_offset = u64::from_le_bytes(bytes[header_end..header_end + 8].try_into().unwrap());
+    assert_eq!((header_end + 16) as u64, first_offset);
+}
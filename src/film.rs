@@ -0,0 +1,204 @@
+use crate::framebuffer::Framebuffer;
+use crate::util::PI;
+use crate::vector::Color;
+
+/// Pixel reconstruction filters. All of them are separable and evaluated
+/// over `-radius..=radius` in pixel units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian { alpha: f64 },
+    MitchellNetravali { b: f64, c: f64 },
+    Lanczos { tau: f64 },
+}
+
+impl Filter {
+    pub fn parse(name: &str) -> Option<Filter> {
+        match name {
+            "box" => Some(Filter::Box),
+            "tent" => Some(Filter::Tent),
+            "gaussian" => Some(Filter::Gaussian { alpha: 2.0 }),
+            "mitchell" => Some(Filter::MitchellNetravali {
+                b: 1.0 / 3.0,
+                c: 1.0 / 3.0,
+            }),
+            "lanczos" => Some(Filter::Lanczos { tau: 3.0 }),
+            _ => None,
+        }
+    }
+
+    pub fn default_radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian { .. } => 1.5,
+            Filter::MitchellNetravali { .. } => 2.0,
+            Filter::Lanczos { .. } => 3.0,
+        }
+    }
+
+    fn evaluate_1d(&self, x: f64, radius: f64) -> f64 {
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => radius - x,
+            Filter::Gaussian { alpha } => {
+                f64::exp(-alpha * x * x) - f64::exp(-alpha * radius * radius)
+            }
+            Filter::MitchellNetravali { b, c } => mitchell_1d(2.0 * x / radius, *b, *c),
+            Filter::Lanczos { tau } => sinc(x) * sinc(x / tau),
+        }
+    }
+
+    pub fn evaluate(&self, dx: f64, dy: f64, radius: f64) -> f64 {
+        self.evaluate_1d(dx, radius) * self.evaluate_1d(dy, radius)
+    }
+}
+
+fn mitchell_1d(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    if x > 1.0 {
+        ((-b - 6.0 * c) * x * x * x
+            + (6.0 * b + 30.0 * c) * x * x
+            + (-12.0 * b - 48.0 * c) * x
+            + (8.0 * b + 24.0 * c))
+            / 6.0
+    } else {
+        ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+            + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+            + (6.0 - 2.0 * b))
+            / 6.0
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    f64::sin(PI * x) / (PI * x)
+}
+
+/// Accumulates filtered samples. Every sample is splatted onto all pixels
+/// whose centre lies within the filter radius, together with its weight.
+pub struct Film {
+    width: usize,
+    height: usize,
+    filter: Filter,
+    radius: f64,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize, filter: Filter, radius: f64) -> Self {
+        Film {
+            width,
+            height,
+            filter,
+            radius,
+            sums: vec![Color::default(); width * height],
+            weights: vec![0.0; width * height],
+        }
+    }
+
+    /// Adds a sample at continuous raster position `(x, y)`, measured in
+    /// pixels from the top left corner of the image.
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let x0 = f64::ceil(x - 0.5 - self.radius).max(0.0) as usize;
+        let y0 = f64::ceil(y - 0.5 - self.radius).max(0.0) as usize;
+        let x1 = f64::floor(x - 0.5 + self.radius).min(self.width as f64 - 1.0);
+        let y1 = f64::floor(y - 0.5 + self.radius).min(self.height as f64 - 1.0);
+        if x1 < 0.0 || y1 < 0.0 {
+            return;
+        }
+
+        for py in y0..=(y1 as usize) {
+            for px in x0..=(x1 as usize) {
+                let dx = x - (px as f64 + 0.5);
+                let dy = y - (py as f64 + 0.5);
+                let weight = self.filter.evaluate(dx, dy, self.radius);
+                if weight == 0.0 {
+                    continue;
+                }
+                let index = py * self.width + px;
+                self.sums[index] = self.sums[index] + weight * color;
+                self.weights[index] += weight;
+            }
+        }
+    }
+
+    /// Normalises the weighted sums into a framebuffer.
+    pub fn resolve(&self) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(self.width, self.height);
+        for (i, (sum, weight)) in self.sums.iter().zip(self.weights.iter()).enumerate() {
+            // Negative lobes can leave a pixel with a tiny or negative total weight
+            if *weight > 1e-8 {
+                framebuffer.pixels[i] = *sum / *weight;
+            }
+        }
+        framebuffer
+    }
+}
+
+#[test]
+fn filters_preserve_constant_image_test() {
+    let filters = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian { alpha: 2.0 },
+        Filter::MitchellNetravali {
+            b: 1.0 / 3.0,
+            c: 1.0 / 3.0,
+        },
+        Filter::Lanczos { tau: 3.0 },
+    ];
+
+    for filter in filters {
+        let mut film = Film::new(4, 4, filter, filter.default_radius());
+        for y in 0..16 {
+            for x in 0..16 {
+                let color = Color::new(0.25, 0.5, 1.0);
+                film.add_sample((x as f64 + 0.5) / 4.0, (y as f64 + 0.5) / 4.0, color);
+            }
+        }
+
+        for pixel in film.resolve().pixels {
+            assert!((pixel - Color::new(0.25, 0.5, 1.0)).length() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn box_filter_stays_inside_pixel_test() {
+    let mut film = Film::new(2, 1, Filter::Box, 0.5);
+    film.add_sample(0.9, 0.5, Color::new(1.0, 1.0, 1.0));
+
+    let framebuffer = film.resolve();
+
+    assert_eq!(Color::new(1.0, 1.0, 1.0), framebuffer.pixels[0]);
+    assert_eq!(Color::default(), framebuffer.pixels[1]);
+}
+
+#[test]
+fn mitchell_weights_test() {
+    // A sample halfway between two pixel centres, weighted by the
+    // Mitchell-Netravali filter with B = C = 1/3: k(0) = 8/9, k(0.5) =
+    // 77/144, k(1) = 1/18 and k(1.5) = -5/144
+    let filter = Filter::parse("mitchell").unwrap();
+    let mut film = Film::new(4, 3, filter, filter.default_radius());
+    film.add_sample(2.0, 1.5, Color::new(1.0, 1.0, 1.0));
+
+    let across = [-5.0 / 144.0, 77.0 / 144.0, 77.0 / 144.0, -5.0 / 144.0];
+    let down = [1.0 / 18.0, 8.0 / 9.0, 1.0 / 18.0];
+    for (y, down) in down.iter().enumerate() {
+        for (x, across) in across.iter().enumerate() {
+            let weight = film.weights[y * 4 + x];
+            assert!((weight - across * down).abs() < 1e-12, "{} {}", x, y);
+        }
+    }
+}
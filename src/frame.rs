@@ -0,0 +1,66 @@
+use crate::util::PI;
+use crate::vector::Vec3;
+
+/// Orthonormal shading frame. In local coordinates the normal is +Z, which
+/// is the convention the microfacet functions work in.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub s: Vec3,
+    pub t: Vec3,
+    pub n: Vec3,
+}
+
+impl Frame {
+    /// Builds a frame around `n` whose tangent follows lines of latitude
+    /// around the Y axis, matching the `u` direction of a sphere.
+    pub fn from_normal(n: Vec3) -> Frame {
+        let mut s = Vec3::new(0.0, 1.0, 0.0).cross(n);
+        if s.length_squared() < 1e-12 {
+            // At the poles any tangent will do
+            s = Vec3::new(1.0, 0.0, 0.0).cross(n);
+        }
+        let s = s.unit_vector();
+        let t = n.cross(s);
+        Frame { s, t, n }
+    }
+
+    pub fn to_local(self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    pub fn to_world(self, v: Vec3) -> Vec3 {
+        v.x * self.s + v.y * self.t + v.z * self.n
+    }
+}
+
+/// Direction in the upper hemisphere with density `cos(theta) / PI`.
+pub fn sample_cosine_hemisphere(u1: f64, u2: f64) -> Vec3 {
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - u1).max(0.0).sqrt())
+}
+
+/// Direction inside the cone of directions around +Z whose angle to the
+/// axis has cosine at least `cos_max`, uniform over solid angle.
+pub fn sample_cone(u1: f64, u2: f64, cos_max: f64) -> Vec3 {
+    let cos_theta = 1.0 - u1 * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Density of `sample_cone` over solid angle.
+pub fn cone_pdf(cos_max: f64) -> f64 {
+    1.0 / (2.0 * PI * (1.0 - cos_max))
+}
+
+#[test]
+fn frame_round_trip_test() {
+    let frame = Frame::from_normal(Vec3::new(1.0, 2.0, 3.0).unit_vector());
+    let v = Vec3::new(0.3, -0.2, 0.9);
+
+    let local = frame.to_local(v);
+
+    assert!((local.z - v.dot(frame.n)).abs() < 1e-12);
+    assert!((frame.to_world(local) - v).length() < 1e-12);
+}
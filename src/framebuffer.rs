@@ -0,0 +1,81 @@
+use crate::tonemap::ToneMapper;
+use crate::util;
+use crate::vector::Color;
+use std::io::{self, Write};
+
+/// Linear radiance for every pixel, stored row by row from the top of the image.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+        }
+    }
+
+    /// Tone maps the buffer and writes it as a plain PPM image.
+    pub fn write_ppm(&self, out: &mut impl Write, tone_mapper: &ToneMapper) -> io::Result<()> {
+        self.write_mapped_ppm(out, |pixel| tone_mapper.map(pixel))
+    }
+
+    /// Writes the values clamped to 0.0 <= x <= 1.0 as a plain PPM image,
+    /// without tone mapping or sRGB encoding, for data such as normals.
+    pub fn write_linear_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        self.write_mapped_ppm(out, |pixel| {
+            Color::new(
+                util::clamp(pixel.x, 0.0, 1.0),
+                util::clamp(pixel.y, 0.0, 1.0),
+                util::clamp(pixel.z, 0.0, 1.0),
+            )
+        })
+    }
+
+    fn write_mapped_ppm(
+        &self,
+        out: &mut impl Write,
+        map: impl Fn(Color) -> Color,
+    ) -> io::Result<()> {
+        writeln!(out, "P3")?;
+        writeln!(out, "{} {}", self.width, self.height)?;
+        writeln!(out, "255")?;
+
+        for pixel in self.pixels.iter() {
+            let display = map(*pixel);
+            writeln!(
+                out,
+                "{} {} {}",
+                quantize(display.x),
+                quantize(display.y),
+                quantize(display.z)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the untouched linear values as a little endian PFM image.
+    pub fn write_pfm(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        // PFM stores rows from the bottom of the image
+        for row in self.pixels.chunks(self.width).rev() {
+            for pixel in row {
+                for value in [pixel.x, pixel.y, pixel.z] {
+                    out.write_all(&(value as f32).to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn quantize(value: f64) -> i32 {
+    (255.0 * value).round() as i32
+}
@@ -0,0 +1,215 @@
+use crate::vector::Color;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Floating point image decoded from a Radiance `.hdr` file, top row first.
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+pub fn load_hdr(path: &str) -> io::Result<HdrImage> {
+    read_hdr(BufReader::new(File::open(path)?))
+}
+
+/// Largest image accepted, well above any environment map in use, so that
+/// a corrupt header cannot ask for an enormous allocation.
+static MAX_PIXELS: usize = 1 << 28;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Decodes an RGBE image in the standard `-Y height +X width` orientation,
+/// with flat, old style or adaptive run length encoded scanlines.
+pub fn read_hdr(mut input: impl BufRead) -> io::Result<HdrImage> {
+    let mut line = String::new();
+    input.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+
+    // Header variables end at the first empty line
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Err(invalid("missing resolution"));
+        }
+        let variable = line.trim();
+        if variable.is_empty() {
+            break;
+        }
+        if let Some(format) = variable.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid("only RGBE pixels are supported"));
+            }
+        }
+    }
+
+    line.clear();
+    input.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (height, width): (usize, usize) = match resolution[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse().map_err(|_| invalid("invalid height"))?,
+            width.parse().map_err(|_| invalid("invalid width"))?,
+        ),
+        _ => return Err(invalid("unsupported image orientation")),
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+    if width
+        .checked_mul(height)
+        .is_none_or(|size| size > MAX_PIXELS)
+    {
+        return Err(invalid("image too large"));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(&mut input, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|rgbe| rgbe_to_color(*rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn read_scanline(input: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut first = [0u8; 4];
+    input.read_exact(&mut first)?;
+
+    let is_adaptive = (8..0x8000).contains(&width)
+        && first[0] == 2
+        && first[1] == 2
+        && ((first[2] as usize) << 8 | first[3] as usize) == width;
+    if !is_adaptive {
+        return read_old_scanline(input, first, scanline);
+    }
+
+    // Each channel is stored separately as runs and literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            input.read_exact(&mut count)?;
+            let count = count[0] as usize;
+            if count > 128 {
+                let run = count - 128;
+                if x + run > width {
+                    return Err(invalid("run overflows the scanline"));
+                }
+                let mut value = [0u8; 1];
+                input.read_exact(&mut value)?;
+                for pixel in scanline[x..x + run].iter_mut() {
+                    pixel[channel] = value[0];
+                }
+                x += run;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("invalid span in scanline"));
+                }
+                let mut values = vec![0u8; count];
+                input.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Flat pixels, where `1 1 1 n` repeats the previous pixel `n` times,
+/// shifted left by 8 bits for every directly preceding repeat.
+fn read_old_scanline(
+    input: &mut impl Read,
+    first: [u8; 4],
+    scanline: &mut [[u8; 4]],
+) -> io::Result<()> {
+    let mut pixel = first;
+    let mut x = 0;
+    let mut shift = 0;
+    loop {
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if x == 0 {
+                return Err(invalid("repeat at the start of a scanline"));
+            }
+            let count = (pixel[3] as usize) << shift;
+            if x + count > scanline.len() {
+                return Err(invalid("run overflows the scanline"));
+            }
+            let previous = scanline[x - 1];
+            scanline[x..x + count].fill(previous);
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x] = pixel;
+            x += 1;
+            shift = 0;
+        }
+
+        if x == scanline.len() {
+            return Ok(());
+        }
+        input.read_exact(&mut pixel)?;
+    }
+}
+
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::default();
+    }
+    let scale = 2f64.powi(e as i32 - (128 + 8));
+    Color::new(
+        (r as f64 + 0.5) * scale,
+        (g as f64 + 0.5) * scale,
+        (b as f64 + 0.5) * scale,
+    )
+}
+
+#[test]
+fn read_hdr_test() {
+    // Two scanlines of width 8, the first adaptive run length encoded with
+    // a run in the red channel, the second flat with an old style repeat
+    let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+    file.extend([2, 2, 0, 8]);
+    file.extend([128 + 8, 128]);
+    file.extend([8, 0, 1, 2, 3, 4, 5, 6, 7]);
+    file.extend([128 + 8, 0]);
+    file.extend([128 + 8, 129]);
+    file.extend([64, 128, 192, 129]);
+    file.extend([1, 1, 1, 7]);
+
+    let image = read_hdr(&file[..]).unwrap();
+
+    assert_eq!((8, 2), (image.width, image.height));
+    assert_eq!(
+        Color::new(128.5 / 128.0, 3.5 / 128.0, 0.5 / 128.0),
+        image.pixel(3, 0)
+    );
+    assert_eq!(
+        Color::new(64.5 / 128.0, 128.5 / 128.0, 192.5 / 128.0),
+        image.pixel(7, 1)
+    );
+
+    for resolution in ["-Y 0 +X 8", "-Y 2 +X 0", "-Y 100000 +X 100000"] {
+        let file = format!("#?RADIANCE\n\n{}\n", resolution);
+        let error = read_hdr(file.as_bytes()).err().unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+    }
+}
@@ -1,5 +1,7 @@
 use crate::material::MaterialEnum;
 use crate::ray::Ray;
+use crate::stats::{self, Counter};
+use crate::util::PI;
 use crate::vector::{Color, Point3, Vec3};
 
 #[derive(Default, Clone)]
@@ -8,7 +10,12 @@ pub struct HitRecord {
     pub normal: Vec3,
     pub material: MaterialEnum,
     pub t: f64,
+    /// Surface coordinates of the hit point.
+    pub u: f64,
+    pub v: f64,
     pub is_front_face: bool,
+    /// Index of the object in the `HittableList` that was hit.
+    pub object_id: usize,
 }
 
 pub trait Hittable {
@@ -39,6 +46,7 @@ impl Default for Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool {
+        stats::increment(Counter::IntersectionTests);
         let oc = ray.orig - self.center;
         let a = ray.dir.length_squared();
         let half_b = oc.dot(ray.dir);
@@ -52,7 +60,7 @@ impl Hittable for Sphere {
 
         let mut root = (-half_b - sqrtd) / a;
         if root < t_min || root > t_max {
-            root = (-half_b - discriminant.sqrt()) / a;
+            root = (-half_b + sqrtd) / a;
             if root < t_min || root > t_max {
                 return false;
             }
@@ -62,12 +70,104 @@ impl Hittable for Sphere {
         hit_record.p = ray.at(hit_record.t);
         let outward_normal_unit = (hit_record.p - self.center) / self.radius;
         hit_record.set_face_normal(ray, &outward_normal_unit);
+        (hit_record.u, hit_record.v) = sphere_uv(&outward_normal_unit);
         hit_record.material = self.material;
 
         true
     }
 }
 
+/// Parallelogram spanned by the edges `u` and `v` from `corner`. Its front
+/// side faces along `u × v`.
+pub struct Quad {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: MaterialEnum,
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool {
+        stats::increment(Counter::IntersectionTests);
+        let n = self.u.cross(self.v);
+        let denominator = n.dot(ray.dir);
+        if denominator.abs() < 1e-12 {
+            return false;
+        }
+        let t = n.dot(self.corner - ray.orig) / denominator;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        // Coordinates of the hit along the two edges
+        let d = ray.at(t) - self.corner;
+        let alpha = n.dot(d.cross(self.v)) / n.length_squared();
+        let beta = n.dot(self.u.cross(d)) / n.length_squared();
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return false;
+        }
+
+        hit_record.t = t;
+        hit_record.p = ray.at(t);
+        hit_record.set_face_normal(ray, &n.unit_vector());
+        (hit_record.u, hit_record.v) = (alpha, beta);
+        hit_record.material = self.material;
+        true
+    }
+}
+
+/// Triangle whose front side faces along `(b - a) × (c - a)`.
+pub struct Triangle {
+    pub a: Point3,
+    pub b: Point3,
+    pub c: Point3,
+    pub material: MaterialEnum,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64, hit_record: &mut HitRecord) -> bool {
+        stats::increment(Counter::IntersectionTests);
+        // Möller-Trumbore
+        let edge1 = self.b - self.a;
+        let edge2 = self.c - self.a;
+        let pvec = ray.dir.cross(edge2);
+        let determinant = edge1.dot(pvec);
+        if determinant.abs() < 1e-12 {
+            return false;
+        }
+        let inverse = 1.0 / determinant;
+        let tvec = ray.orig - self.a;
+        let u = tvec.dot(pvec) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return false;
+        }
+        let qvec = tvec.cross(edge1);
+        let v = ray.dir.dot(qvec) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return false;
+        }
+        let t = edge2.dot(qvec) * inverse;
+        if t < t_min || t > t_max {
+            return false;
+        }
+
+        hit_record.t = t;
+        hit_record.p = ray.at(t);
+        hit_record.set_face_normal(ray, &edge1.cross(edge2).unit_vector());
+        (hit_record.u, hit_record.v) = (u, v);
+        hit_record.material = self.material;
+        true
+    }
+}
+
+/// Spherical coordinates of a point on the unit sphere, mapped to 0.0..=1.0.
+/// `u` runs around the Y axis starting at -X, `v` from the bottom to the top.
+fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = f64::acos(-p.y);
+    let phi = f64::atan2(-p.z, p.x) + PI;
+    (phi / (2.0 * PI), theta / PI)
+}
+
 impl HitRecord {
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
         self.is_front_face = ray.dir.dot(*outward_normal) < 0.0;
@@ -89,14 +189,31 @@ impl Hittable for HittableList {
         let mut hits_anything = false;
         let mut closest_so_far = t_max;
 
-        for obj in self.objects.iter() {
+        for (object_id, obj) in self.objects.iter().enumerate() {
             if obj.hit(ray, t_min, closest_so_far, &mut temp_record) {
                 hits_anything = true;
+                temp_record.object_id = object_id;
                 *hit_record = temp_record.clone();
                 closest_so_far = temp_record.t;
             }
         }
 
-        return hits_anything;
+        hits_anything
     }
 }
+
+#[test]
+fn sphere_far_root_test() {
+    // From inside, the near root is behind the ray and the far one is hit
+    let sphere = Sphere {
+        center: Point3::new(0.0, 0.0, 0.0),
+        radius: 2.0,
+        ..Sphere::default()
+    };
+    let ray = Ray::new(Point3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut hit_record = HitRecord::default();
+    assert!(sphere.hit(&ray, 0.001, crate::util::INFINITY, &mut hit_record));
+    assert_eq!(3.0, hit_record.t);
+    assert_eq!(Point3::new(0.0, 0.0, -2.0), hit_record.p);
+    assert!(!hit_record.is_front_face);
+}
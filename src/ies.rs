@@ -0,0 +1,125 @@
+use crate::util::PI;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Rotationally symmetric intensity distribution of a luminaire, relative to
+/// its brightest direction.
+pub struct LightProfile {
+    /// Angles from the light's axis in radians, increasing.
+    angles: Vec<f64>,
+    /// Relative intensity at each angle, at most 1.
+    values: Vec<f64>,
+}
+
+impl LightProfile {
+    /// Relative intensity at `angle` radians from the axis, interpolated
+    /// linearly and zero outside the measured range.
+    pub fn value(&self, angle: f64) -> f64 {
+        let last = self.angles.len() - 1;
+        if angle < self.angles[0] || angle > self.angles[last] {
+            return 0.0;
+        }
+        let i = self
+            .angles
+            .partition_point(|a| *a <= angle)
+            .clamp(1, last.max(1))
+            - 1;
+        if i == last {
+            return self.values[last];
+        }
+        let t = (angle - self.angles[i]) / (self.angles[i + 1] - self.angles[i]);
+        self.values[i] * (1.0 - t) + self.values[i + 1] * t
+    }
+}
+
+pub fn load_ies(path: &str) -> io::Result<LightProfile> {
+    read_ies(BufReader::new(File::open(path)?))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads an IESNA LM-63 photometric file. Vertical angle 0 becomes the
+/// light's axis and the candela values are averaged over the horizontal
+/// angles.
+pub fn read_ies(input: impl BufRead) -> io::Result<LightProfile> {
+    let mut lines = input.lines();
+    // Keywords come before the tilt line, the numbers after it
+    let tilt = loop {
+        let line = lines.next().ok_or_else(|| invalid("missing TILT line"))??;
+        if let Some(tilt) = line.trim().strip_prefix("TILT=") {
+            break tilt.to_string();
+        }
+    };
+    let mut numbers: Vec<f64> = Vec::new();
+    for line in lines {
+        for token in line?.split(|c: char| c.is_whitespace() || c == ',') {
+            if !token.is_empty() {
+                numbers.push(token.parse().map_err(|_| invalid("invalid number"))?);
+            }
+        }
+    }
+    let mut numbers = numbers.into_iter();
+    let mut next = || numbers.next().ok_or_else(|| invalid("file ends early"));
+
+    if tilt == "INCLUDE" {
+        // Lamp geometry, then pairs of angles and multipliers
+        next()?;
+        let count = next()? as usize;
+        for _ in 0..2 * count {
+            next()?;
+        }
+    } else if tilt != "NONE" {
+        return Err(invalid("tilt files are not supported"));
+    }
+
+    let _lamps = next()?;
+    let _lumens_per_lamp = next()?;
+    let multiplier = next()?;
+    let vertical_count = next()? as usize;
+    let horizontal_count = next()? as usize;
+    // Photometric type, units, dimensions, ballast factors and watts
+    for _ in 0..8 {
+        next()?;
+    }
+    if vertical_count == 0 || horizontal_count == 0 {
+        return Err(invalid("no measurements"));
+    }
+
+    let mut angles = Vec::with_capacity(vertical_count);
+    for _ in 0..vertical_count {
+        angles.push(next()? * PI / 180.0);
+    }
+    for _ in 0..horizontal_count {
+        next()?;
+    }
+    let mut values = vec![0.0; vertical_count];
+    for _ in 0..horizontal_count {
+        for value in values.iter_mut() {
+            *value += next()? * multiplier / horizontal_count as f64;
+        }
+    }
+
+    let max = values.iter().cloned().fold(0.0, f64::max);
+    if max <= 0.0 {
+        return Err(invalid("the light emits nothing"));
+    }
+    values.iter_mut().for_each(|value| *value /= max);
+    Ok(LightProfile { angles, values })
+}
+
+#[test]
+fn read_ies_test() {
+    let file = b"IESNA:LM-63-2002\n[TEST] downlight\nTILT=NONE\n\
+        1 1000 2 3 2 1 2 0 0 0\n1 1 100\n\
+        0 30 90\n0 180\n\
+        100 50 0\n300, 150, 0\n";
+
+    let profile = read_ies(&file[..]).unwrap();
+
+    assert_eq!(1.0, profile.value(0.0));
+    assert!((profile.value(PI / 12.0) - 0.75).abs() < 1e-12);
+    assert!((profile.value(PI / 3.0) - 0.25).abs() < 1e-12);
+    assert_eq!(0.0, profile.value(PI));
+}
@@ -0,0 +1,331 @@
+use crate::aperture::Aperture;
+use crate::util;
+use crate::vector::{Point3, Vec3};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Number of rings across the film with their own exit pupil.
+const PUPIL_RINGS: usize = 64;
+/// Points per side of the grid searched for the exit pupil of a ring.
+const PUPIL_GRID: usize = 64;
+
+/// One spherical surface of a lens, in metres.
+struct LensElement {
+    /// Radius of curvature, positive when the surface bulges towards the
+    /// scene, 0 for the aperture stop.
+    curvature_radius: f64,
+    /// Distance along the axis to the next surface towards the film.
+    thickness: f64,
+    /// Index of refraction between this surface and the next.
+    ior: f64,
+    aperture_radius: f64,
+}
+
+/// Lens made of spherical elements, traced from the film out into the
+/// scene. The film is at the origin, facing down the negative Z axis.
+pub struct LensSystem {
+    /// Front element first.
+    elements: Vec<LensElement>,
+    /// Distance from the rear element to the film, set when focusing.
+    film_distance: f64,
+    sensor_diagonal: f64,
+    /// Region of the rear element that light from each ring of the film
+    /// gets through, as `(min_x, min_y, max_x, max_y)` for points on the
+    /// positive X axis.
+    exit_pupils: [(f64, f64, f64, f64); PUPIL_RINGS],
+    /// Pupil area the sample weights are relative to, that of the centre
+    /// of the film or, when no light reaches it, the largest one.
+    reference_pupil_area: f64,
+}
+
+pub fn load_lens(path: &str, sensor_diagonal: f64) -> io::Result<LensSystem> {
+    read_lens(BufReader::new(File::open(path)?), sensor_diagonal)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads a lens prescription with one surface per line, front first, as
+/// `radius thickness ior aperture_diameter` in millimetres. Lines starting
+/// with `#` are comments. The thickness of the last surface is replaced
+/// when the lens is focused.
+pub fn read_lens(input: impl BufRead, sensor_diagonal: f64) -> io::Result<LensSystem> {
+    let mut elements = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let numbers: Vec<f64> = line
+            .split_whitespace()
+            .map(|token| token.parse().map_err(|_| invalid("invalid number")))
+            .collect::<io::Result<_>>()?;
+        let [radius, thickness, ior, diameter] = numbers[..] else {
+            return Err(invalid("expected radius, thickness, ior and aperture"));
+        };
+        elements.push(LensElement {
+            curvature_radius: radius * 0.001,
+            thickness: thickness * 0.001,
+            // Air is written as 0 in some prescriptions
+            ior: if ior == 0.0 { 1.0 } else { ior },
+            aperture_radius: diameter * 0.0005,
+        });
+    }
+    if elements.is_empty() {
+        return Err(invalid("no lens elements"));
+    }
+    let film_distance = elements.last().unwrap().thickness;
+    Ok(LensSystem {
+        elements,
+        film_distance,
+        sensor_diagonal: sensor_diagonal * 0.001,
+        exit_pupils: [(0.0, 0.0, 0.0, 0.0); PUPIL_RINGS],
+        reference_pupil_area: 0.0,
+    })
+}
+
+impl LensSystem {
+    /// Moves the film until points `distance` metres in front of it are
+    /// sharp, then finds the exit pupils.
+    pub fn focus(&mut self, distance: f64) {
+        // Moving the film back brings the plane of focus closer
+        let (mut near, mut far) = (0.0, self.film_distance.max(0.001));
+        while self.object_distance(far) > distance && far < 1.0 {
+            far *= 2.0;
+        }
+        for _ in 0..60 {
+            let middle = 0.5 * (near + far);
+            if self.object_distance(middle) > distance {
+                near = middle;
+            } else {
+                far = middle;
+            }
+        }
+        self.film_distance = far;
+        self.exit_pupils = std::array::from_fn(|ring| self.find_exit_pupil(ring));
+        self.reference_pupil_area = match self.exit_pupil_area(0) {
+            area if area > 0.0 => area,
+            _ => (0..PUPIL_RINGS)
+                .map(|ring| self.exit_pupil_area(ring))
+                .fold(0.0, f64::max),
+        };
+    }
+
+    /// Distance from the film at which a thin bundle of rays from its
+    /// centre meets again, infinite when they spread out.
+    fn object_distance(&self, film_distance: f64) -> f64 {
+        let height = 0.001 * self.elements.last().unwrap().aperture_radius;
+        let direction = Vec3::new(height, 0.0, -film_distance);
+        match self.trace(film_distance, Point3::default(), direction, &mut |_, _| {
+            true
+        }) {
+            Some((origin, direction)) if origin.x * direction.x < 0.0 => {
+                -(origin.z - origin.x / direction.x * direction.z)
+            }
+            _ => util::INFINITY,
+        }
+    }
+
+    fn rear_z(&self) -> f64 {
+        -self.film_distance
+    }
+
+    /// Bounds of the rear element points through which light reaches film
+    /// points on the X axis in `ring`, from a grid of test rays. The stop
+    /// is taken as a square so that every aperture shape fits inside.
+    fn find_exit_pupil(&self, ring: usize) -> (f64, f64, f64, f64) {
+        let ring_width = 0.5 * self.sensor_diagonal / PUPIL_RINGS as f64;
+        let extent = 1.5 * self.elements.last().unwrap().aperture_radius;
+        let cell = 2.0 * extent / PUPIL_GRID as f64;
+        let mut pupil = (
+            util::INFINITY,
+            util::INFINITY,
+            -util::INFINITY,
+            -util::INFINITY,
+        );
+        for i in 0..PUPIL_GRID {
+            for j in 0..PUPIL_GRID {
+                let x = -extent + (i as f64 + 0.5) * cell;
+                let y = -extent + (j as f64 + 0.5) * cell;
+                let passes = [0.0, 0.5, 1.0].iter().any(|t| {
+                    let film = Point3::new((ring as f64 + t) * ring_width, 0.0, 0.0);
+                    let direction = Point3::new(x, y, self.rear_z()) - film;
+                    self.trace(self.film_distance, film, direction, &mut |x, y| {
+                        x.abs() <= 1.0 && y.abs() <= 1.0
+                    })
+                    .is_some()
+                });
+                if passes {
+                    pupil = (
+                        pupil.0.min(x - cell),
+                        pupil.1.min(y - cell),
+                        pupil.2.max(x + cell),
+                        pupil.3.max(y + cell),
+                    );
+                }
+            }
+        }
+        pupil
+    }
+
+    /// Area of the exit pupil bounds of `ring`, 0 when no light gets through.
+    fn exit_pupil_area(&self, ring: usize) -> f64 {
+        let (min_x, min_y, max_x, max_y) = self.exit_pupils[ring];
+        if min_x > max_x {
+            return 0.0;
+        }
+        (max_x - min_x) * (max_y - min_y)
+    }
+
+    /// Ray leaving the front of the lens for light reaching the film point
+    /// `(x, y)` and its weight, or `None` when it is blocked inside the
+    /// lens. The stop has the shape of `aperture`.
+    ///
+    /// The weight is the irradiance term cos⁴θ·A/d² of the sampled pupil
+    /// area A seen from the film at distance d, divided by its value at the
+    /// centre of the film so that the middle of the image keeps its
+    /// brightness and only the corners darken. An obstructed centre is
+    /// compared with the largest pupil instead.
+    pub fn ray(&self, x: f64, y: f64, aperture: &Aperture) -> Option<(Point3, Vec3, f64)> {
+        let r = (x * x + y * y).sqrt();
+        let ring =
+            ((r / (0.5 * self.sensor_diagonal) * PUPIL_RINGS as f64) as usize).min(PUPIL_RINGS - 1);
+        let (min_x, min_y, max_x, max_y) = self.exit_pupils[ring];
+        if min_x > max_x {
+            return None;
+        }
+        // The pupils are found on the X axis, turn them to the film point
+        let px = util::random_double_rng(min_x, max_x);
+        let py = util::random_double_rng(min_y, max_y);
+        let (sin, cos) = if r > 0.0 { (y / r, x / r) } else { (0.0, 1.0) };
+        let rear = Point3::new(cos * px - sin * py, sin * px + cos * py, self.rear_z());
+        let film = Point3::new(x, y, 0.0);
+        let (origin, direction) =
+            self.trace(self.film_distance, film, rear - film, &mut |x, y| {
+                util::random_double() < aperture.transmission(x, y)
+            })?;
+        // d² is the same over the whole film and cancels
+        let cos_theta = (rear - film).unit_vector().z.abs();
+        let weight = cos_theta.powi(4) * self.exit_pupil_area(ring) / self.reference_pupil_area;
+        Some((origin, direction, weight))
+    }
+
+    /// Refracts a ray from the film through every surface, back to front.
+    /// `stop` is asked whether light passes a point of the aperture stop,
+    /// relative to its radius.
+    fn trace(
+        &self,
+        film_distance: f64,
+        mut origin: Point3,
+        direction: Vec3,
+        stop: &mut impl FnMut(f64, f64) -> bool,
+    ) -> Option<(Point3, Vec3)> {
+        let mut direction = direction.unit_vector();
+        let mut z = -film_distance;
+        for (i, element) in self.elements.iter().enumerate().rev() {
+            if i + 1 < self.elements.len() {
+                z -= element.thickness;
+            }
+            let radius = element.curvature_radius;
+            if radius == 0.0 {
+                let t = (z - origin.z) / direction.z;
+                if t <= 0.0 {
+                    return None;
+                }
+                origin = origin + t * direction;
+                if !stop(
+                    origin.x / element.aperture_radius,
+                    origin.y / element.aperture_radius,
+                ) {
+                    return None;
+                }
+                continue;
+            }
+
+            let center = Point3::new(0.0, 0.0, z + radius);
+            let oc = origin - center;
+            let b = oc.dot(direction);
+            let discriminant = b * b - (oc.length_squared() - radius * radius);
+            if discriminant < 0.0 {
+                return None;
+            }
+            // Going towards the scene, a surface bulging that way is met
+            // from inside its sphere
+            let root = discriminant.sqrt();
+            let t = if (direction.z > 0.0) ^ (radius < 0.0) {
+                -b - root
+            } else {
+                -b + root
+            };
+            if t <= 0.0 {
+                return None;
+            }
+            origin = origin + t * direction;
+            if origin.x * origin.x + origin.y * origin.y > element.aperture_radius.powi(2) {
+                return None;
+            }
+
+            let mut normal = (origin - center).unit_vector();
+            if normal.dot(direction) > 0.0 {
+                normal = -normal;
+            }
+            let ior_after = if i > 0 { self.elements[i - 1].ior } else { 1.0 };
+            let eta = element.ior / ior_after;
+            let cos_theta = -direction.dot(normal);
+            if eta * eta * (1.0 - cos_theta * cos_theta) > 1.0 {
+                return None;
+            }
+            direction = Vec3::refract(direction, normal, eta).unit_vector();
+        }
+        Some((origin, direction))
+    }
+
+    /// Width and height of the sensor in metres.
+    pub fn film_size(&self, aspect_ratio: f64) -> (f64, f64) {
+        let height = self.sensor_diagonal / (1.0 + aspect_ratio * aspect_ratio).sqrt();
+        (aspect_ratio * height, height)
+    }
+}
+
+#[test]
+fn lens_test() {
+    // Biconvex singlet with a focal length near 50 mm, stopped down to
+    // f/25 in front to keep spherical aberration small
+    let prescription = "# radius thickness ior aperture\n\
+                        0 2 1 2\n\
+                        51.5 5 1.5 20\n\
+                        -51.5 48 1 20\n";
+    let mut lens = read_lens(prescription.as_bytes(), 43.27).unwrap();
+    lens.focus(2.0);
+    assert!((lens.object_distance(lens.film_distance) - 2.0).abs() < 1e-6);
+    assert!(lens.film_distance > 0.050 && lens.film_distance < 0.055);
+
+    // Light from the centre of the film meets again on the axis 2 m away
+    let spread = |lens: &LensSystem| {
+        (0..100)
+            .filter_map(|_| lens.ray(0.0, 0.0, &Aperture::Circle))
+            .map(|(origin, direction, _)| {
+                let p = origin + (-2.0 - origin.z) / direction.z * direction;
+                (p.x * p.x + p.y * p.y).sqrt()
+            })
+            .fold(0.0, f64::max)
+    };
+    let sharp = spread(&lens);
+    lens.focus(4.0);
+    let blurred = spread(&lens);
+    assert!(sharp < 0.2 * blurred, "{} {}", sharp, blurred);
+
+    // The corner of the film is still covered, but gets less light
+    let (width, height) = lens.film_size(1.5);
+    let covered = |x, y| {
+        (0..20)
+            .find_map(|_| lens.ray(x, y, &Aperture::Circle))
+            .unwrap()
+            .2
+    };
+    let (center, corner) = (covered(0.0, 0.0), covered(0.45 * width, 0.45 * height));
+    assert!((center - 1.0).abs() < 0.01, "{}", center);
+    assert!(corner > 0.0 && corner < 0.9, "{}", corner);
+}
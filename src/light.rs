@@ -0,0 +1,258 @@
+use crate::bounds::Bounds;
+use crate::ies::LightProfile;
+use crate::lightsampler::LightBounds;
+use crate::shape::Shape;
+use crate::util::{self, PI};
+use crate::vector::{luminance, Color, Point3, Vec3};
+
+/// Light source sampled for direct lighting. All but area lights are delta
+/// lights, which are not part of the geometry and can only be reached by
+/// sampling them, never by a ray.
+pub enum Light {
+    /// Shines equally in all directions from a point.
+    Point { position: Point3, intensity: Color },
+    /// Point light limited to a cone around `direction`.
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        /// Cosine of the angle up to which the spot has full intensity.
+        cos_inner: f64,
+        /// Cosine of the angle where the spot has faded out.
+        cos_outer: f64,
+        /// Measured intensity distribution, relative to the axis.
+        profile: Option<LightProfile>,
+    },
+    /// Parallel light arriving from `direction`, like a distant sun.
+    Directional { direction: Vec3, irradiance: Color },
+    /// Object with an emissive material, which rays can also hit.
+    Area {
+        shape: Shape,
+        radiance: Color,
+        /// Index of the object in the `HittableList`.
+        object_id: usize,
+    },
+}
+
+/// Light arriving at a shading point from one light.
+pub struct LightSample {
+    /// Unit direction from the shading point towards the light.
+    pub direction: Vec3,
+    /// Distance to the light, infinite for directional lights.
+    pub distance: f64,
+    /// Radiance arriving from the light. For delta lights this is the
+    /// irradiance on a surface facing the light instead.
+    pub radiance: Color,
+    /// Density of `direction` over solid angle, `None` for delta lights.
+    pub pdf: Option<f64>,
+}
+
+impl Light {
+    pub fn spot(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        outer_angle: f64,
+        inner_angle: f64,
+        profile: Option<LightProfile>,
+    ) -> Light {
+        Light::Spot {
+            position,
+            direction: direction.unit_vector(),
+            intensity,
+            cos_inner: util::degrees_to_radians(inner_angle.min(outer_angle)).cos(),
+            cos_outer: util::degrees_to_radians(outer_angle).cos(),
+            profile,
+        }
+    }
+
+    /// Light reaching `p` from a point on the light picked with the uniform
+    /// random numbers `u`, ignoring anything in between. `None` when the
+    /// light sends nothing towards `p`.
+    pub fn sample(&self, p: Point3, u: [f64; 2]) -> Option<LightSample> {
+        let (direction, distance, irradiance) = match self {
+            Light::Point {
+                position,
+                intensity,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                (
+                    to_light / distance,
+                    distance,
+                    *intensity / (distance * distance),
+                )
+            }
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+                profile,
+            } => {
+                let to_light = *position - p;
+                let distance = to_light.length();
+                let cos_theta = (-to_light / distance).dot(*direction);
+                let mut scale = falloff(cos_theta, *cos_inner, *cos_outer);
+                if let Some(profile) = profile {
+                    scale *= profile.value(util::clamp(cos_theta, -1.0, 1.0).acos());
+                }
+                (
+                    to_light / distance,
+                    distance,
+                    scale * *intensity / (distance * distance),
+                )
+            }
+            Light::Directional {
+                direction,
+                irradiance,
+            } => (direction.unit_vector(), util::INFINITY, *irradiance),
+            Light::Area {
+                shape, radiance, ..
+            } => {
+                let sample = shape.sample(p, u)?;
+                // Only the front side emits
+                if sample.normal.dot(sample.direction) >= 0.0 {
+                    return None;
+                }
+                return Some(LightSample {
+                    direction: sample.direction,
+                    distance: sample.distance,
+                    radiance: *radiance,
+                    pdf: Some(sample.pdf),
+                });
+            }
+        };
+
+        if irradiance == Color::default() {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: irradiance,
+            pdf: None,
+        })
+    }
+
+    /// Density of `sample` picking a direction from `p` that reaches the
+    /// light, zero for delta lights.
+    pub fn pdf(&self, p: Point3) -> f64 {
+        match self {
+            Light::Area { shape, .. } => shape.pdf(p),
+            _ => 0.0,
+        }
+    }
+
+    /// Total power emitted, as luminance. Directional lights shine on the
+    /// whole scene and have no finite power.
+    pub fn power(&self) -> f64 {
+        match self {
+            Light::Point { intensity, .. } => 4.0 * PI * luminance(*intensity),
+            Light::Spot {
+                intensity,
+                cos_inner,
+                cos_outer,
+                ..
+            } => 2.0 * PI * luminance(*intensity) * (1.0 - 0.5 * (cos_inner + cos_outer)),
+            Light::Directional { .. } => util::INFINITY,
+            Light::Area {
+                shape, radiance, ..
+            } => PI * luminance(*radiance) * shape.area(),
+        }
+    }
+
+    /// Where the light is and which way it shines, `None` for directional
+    /// lights which are everywhere.
+    pub fn bounds(&self) -> Option<LightBounds> {
+        let any_direction = Vec3::new(0.0, 0.0, 1.0);
+        match self {
+            Light::Point {
+                position,
+                intensity,
+            } => Some(LightBounds {
+                bounds: Bounds::from_points(&[*position]),
+                phi: luminance(*intensity),
+                w: any_direction,
+                cos_theta_o: -1.0,
+                cos_theta_e: 0.0,
+            }),
+            Light::Spot {
+                position,
+                direction,
+                intensity,
+                cos_inner,
+                cos_outer,
+                ..
+            } => Some(LightBounds {
+                bounds: Bounds::from_points(&[*position]),
+                phi: luminance(*intensity),
+                w: *direction,
+                cos_theta_o: *cos_inner,
+                cos_theta_e: (cos_outer.acos() - cos_inner.acos()).cos(),
+            }),
+            Light::Directional { .. } => None,
+            Light::Area {
+                shape, radiance, ..
+            } => {
+                let (phi, w, cos_theta_o) = match *shape {
+                    Shape::Sphere { radius, .. } => (PI * radius * radius, any_direction, -1.0),
+                    Shape::Quad { u, v, .. } => (shape.area(), u.cross(v).unit_vector(), 1.0),
+                    Shape::Triangle { a, b, c } => {
+                        (shape.area(), (b - a).cross(c - a).unit_vector(), 1.0)
+                    }
+                };
+                Some(LightBounds {
+                    bounds: shape.bounds(),
+                    phi: phi * luminance(*radiance),
+                    w,
+                    cos_theta_o,
+                    cos_theta_e: 0.0,
+                })
+            }
+        }
+    }
+}
+
+/// Smooth fade from full intensity inside the inner cone to nothing outside
+/// the outer one.
+fn falloff(cos_theta: f64, cos_inner: f64, cos_outer: f64) -> f64 {
+    if cos_theta >= cos_inner {
+        return 1.0;
+    }
+    if cos_theta <= cos_outer {
+        return 0.0;
+    }
+    let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[test]
+fn spot_light_falloff_test() {
+    let spot = Light::spot(
+        Point3::new(0.0, 2.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+        Color::new(4.0, 4.0, 4.0),
+        30.0,
+        20.0,
+        None,
+    );
+
+    let below = spot.sample(Point3::new(0.0, 0.0, 0.0), [0.5, 0.5]).unwrap();
+    assert_eq!(Color::new(1.0, 1.0, 1.0), below.radiance);
+    assert_eq!(Vec3::new(0.0, 1.0, 0.0), below.direction);
+    assert_eq!(2.0, below.distance);
+
+    // Inside the fade, between 20 and 30 degrees off the axis
+    let edge = spot.sample(
+        Point3::new(2.0 * 25f64.to_radians().tan(), 0.0, 0.0),
+        [0.5, 0.5],
+    );
+    let irradiance = edge.unwrap().radiance.x;
+    assert!(irradiance > 0.0 && irradiance < 1.0);
+
+    assert!(spot
+        .sample(Point3::new(2.0, 0.0, 0.0), [0.5, 0.5])
+        .is_none());
+}
@@ -0,0 +1,512 @@
+use crate::bounds::{self, Bounds};
+use crate::distribution::Distribution1D;
+use crate::light::Light;
+use crate::stats::{self, Counter};
+use crate::util;
+use crate::vector::{Point3, Vec3};
+use std::collections::HashMap;
+
+/// How the light that receives the shadow ray of a bounce is picked.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LightSampling {
+    /// Every light is equally likely.
+    Uniform,
+    /// In proportion to the power each light emits.
+    Power,
+    /// By the light each group of lights is estimated to send towards the
+    /// shading point, descending a bounding volume hierarchy.
+    Bvh,
+}
+
+impl LightSampling {
+    pub fn parse(name: &str) -> Option<LightSampling> {
+        match name {
+            "uniform" => Some(LightSampling::Uniform),
+            "power" => Some(LightSampling::Power),
+            "bvh" => Some(LightSampling::Bvh),
+            _ => None,
+        }
+    }
+}
+
+/// Where a group of lights is and which way it emits, enough to bound the
+/// light it can send towards a point.
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub bounds: Bounds,
+    /// Largest radiant intensity in any direction, as luminance.
+    pub phi: f64,
+    /// Axis of the cone holding the directions of peak emission.
+    pub w: Vec3,
+    /// Cosine of the half angle of that cone.
+    pub cos_theta_o: f64,
+    /// Cosine of the angle beyond the cone at which emission has ended.
+    pub cos_theta_e: f64,
+}
+
+impl LightBounds {
+    fn union(self, other: LightBounds) -> LightBounds {
+        let (w, cos_theta_o) = cone_union((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+        LightBounds {
+            bounds: self.bounds.union(other.bounds),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: self.cos_theta_e.min(other.cos_theta_e),
+        }
+    }
+
+    /// Surface area orientation heuristic of Conty Estevez and Kulla, how
+    /// likely the lights are to matter anywhere.
+    fn cost(&self) -> f64 {
+        let theta_o = util::clamp(self.cos_theta_o, -1.0, 1.0).acos();
+        let theta_e = util::clamp(self.cos_theta_e, -1.0, 1.0).acos();
+        let theta_w = (theta_o + theta_e).min(util::PI);
+        let sin_theta_o = theta_o.sin();
+        // Solid angle of the emission cone weighted by the falloff outside it
+        let m_omega = 2.0 * util::PI * (1.0 - self.cos_theta_o)
+            + 0.5
+                * util::PI
+                * (2.0 * theta_w * sin_theta_o
+                    - (theta_o - 2.0 * theta_w).cos()
+                    - 2.0 * theta_o * sin_theta_o
+                    + self.cos_theta_o);
+        self.phi * m_omega * self.bounds.surface_area()
+    }
+
+    /// Conservative estimate of the irradiance the lights give a surface at
+    /// `p` facing `n`, following the light BVH of Conty Estevez and Kulla.
+    fn importance(&self, p: Point3, n: Vec3) -> f64 {
+        let center = self.bounds.center();
+        let radius = 0.5 * self.bounds.diagonal().length();
+        let d2 = (p - center)
+            .length_squared()
+            .max(radius * radius)
+            .max(1e-12);
+        // Angle the box covers as seen from `p`
+        let sin_theta_b2 = radius * radius / (p - center).length_squared();
+        if self.bounds.contains(p) || sin_theta_b2 >= 1.0 {
+            return self.phi / d2;
+        }
+        let (sin_theta_b, cos_theta_b) = (sin_theta_b2.sqrt(), (1.0 - sin_theta_b2).sqrt());
+
+        // Smallest angle between the emission cone and the direction to `p`,
+        // widened by the angle the box covers
+        let wi = (p - center).unit_vector();
+        let cos_theta_w = self.w.dot(wi);
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let (sin_theta_x, cos_theta_x) = angle_sub_clamped(
+            (sin_from_cos(cos_theta_w), cos_theta_w),
+            (sin_theta_o, self.cos_theta_o),
+        );
+        let (_, cos_theta) =
+            angle_sub_clamped((sin_theta_x, cos_theta_x), (sin_theta_b, cos_theta_b));
+        if cos_theta <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let cos_theta_i = wi.dot(n).abs();
+        let (_, cos_theta_i) = angle_sub_clamped(
+            (sin_from_cos(cos_theta_i), cos_theta_i),
+            (sin_theta_b, cos_theta_b),
+        );
+        (self.phi * cos_theta * cos_theta_i / d2).max(0.0)
+    }
+}
+
+fn sin_from_cos(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta * cos_theta).max(0.0).sqrt()
+}
+
+/// Sine and cosine of `max(a - b, 0)` for angles given by their sine and
+/// cosine.
+fn angle_sub_clamped(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let ((sin_a, cos_a), (sin_b, cos_b)) = (a, b);
+    if cos_a > cos_b {
+        return (0.0, 1.0);
+    }
+    (sin_a * cos_b - cos_a * sin_b, cos_a * cos_b + sin_a * sin_b)
+}
+
+/// Smallest cone, given by its axis and the cosine of its half angle, that
+/// holds both cones.
+fn cone_union(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    let theta_a = util::clamp(a.1, -1.0, 1.0).acos();
+    let theta_b = util::clamp(b.1, -1.0, 1.0).acos();
+    let theta_d = util::clamp(a.0.dot(b.0), -1.0, 1.0).acos();
+    if (theta_d + theta_b).min(util::PI) <= theta_a {
+        return a;
+    }
+    if (theta_d + theta_a).min(util::PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = 0.5 * (theta_a + theta_d + theta_b);
+    let axis = a.0.cross(b.0);
+    if theta_o >= util::PI || axis.length_squared() == 0.0 {
+        return (a.0, -1.0);
+    }
+    // Turn the axis of `a` towards `b` until the cone just holds both
+    let k = axis.unit_vector();
+    let theta_r = theta_o - theta_a;
+    let w = a.0 * theta_r.cos() + k.cross(a.0) * theta_r.sin();
+    (w, theta_o.cos())
+}
+
+enum Node {
+    Leaf {
+        bounds: LightBounds,
+        light: usize,
+    },
+    /// The first child follows its parent directly.
+    Interior {
+        bounds: LightBounds,
+        second_child: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> &LightBounds {
+        match self {
+            Node::Leaf { bounds, .. } | Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// Axis and position in the lights sorted along it that split them into
+/// the two groups of lowest total cost.
+fn best_split(lights: &[(usize, LightBounds)], bounds: &Bounds) -> (usize, usize) {
+    let mut best = (bounds.largest_axis(), lights.len() / 2, util::INFINITY);
+    let diagonal = bounds.diagonal();
+    let longest = diagonal.x.max(diagonal.y).max(diagonal.z);
+    for axis in 0..3 {
+        let extent = bounds::axis(diagonal, axis);
+        if extent <= 0.0 {
+            continue;
+        }
+        let mut sorted = lights.to_vec();
+        sorted.sort_unstable_by(|(_, a), (_, b)| {
+            bounds::axis(a.bounds.center(), axis).total_cmp(&bounds::axis(b.bounds.center(), axis))
+        });
+        // Cost of the lights from each position on, swept from the back
+        let mut suffix_costs = vec![0.0; sorted.len()];
+        let mut suffix: Option<LightBounds> = None;
+        for i in (1..sorted.len()).rev() {
+            let bounds = suffix.map_or(sorted[i].1, |suffix| suffix.union(sorted[i].1));
+            suffix_costs[i] = bounds.cost();
+            suffix = Some(bounds);
+        }
+        let mut prefix: Option<LightBounds> = None;
+        for i in 1..sorted.len() {
+            let bounds = prefix.map_or(sorted[i - 1].1, |prefix| prefix.union(sorted[i - 1].1));
+            prefix = Some(bounds);
+            // Thin boxes are split along their long side
+            let cost = (bounds.cost() + suffix_costs[i]) * longest / extent;
+            if cost < best.2 {
+                best = (axis, i, cost);
+            }
+        }
+    }
+    (best.0, best.1)
+}
+
+/// Binary tree over the lights that have bounds, split where the surface
+/// area and spread of emitted directions of the two halves is smallest.
+struct LightBvh {
+    nodes: Vec<Node>,
+    /// Parent of each node, the root is its own parent.
+    parents: Vec<usize>,
+    /// Leaf node of each light.
+    leaves: HashMap<usize, usize>,
+}
+
+impl LightBvh {
+    fn new(mut lights: Vec<(usize, LightBounds)>) -> LightBvh {
+        let mut bvh = LightBvh {
+            nodes: Vec::new(),
+            parents: Vec::new(),
+            leaves: HashMap::new(),
+        };
+        bvh.build(&mut lights, 0);
+        bvh
+    }
+
+    fn build(&mut self, lights: &mut [(usize, LightBounds)], parent: usize) -> usize {
+        let index = self.nodes.len();
+        self.parents.push(parent);
+        if let [(light, bounds)] = *lights {
+            self.nodes.push(Node::Leaf { bounds, light });
+            self.leaves.insert(light, index);
+            return index;
+        }
+
+        let bounds = lights[1..]
+            .iter()
+            .fold(lights[0].1, |bounds, (_, light)| bounds.union(*light));
+        let (split_axis, middle) = best_split(lights, &bounds.bounds);
+        lights.sort_unstable_by(|(_, a), (_, b)| {
+            bounds::axis(a.bounds.center(), split_axis)
+                .total_cmp(&bounds::axis(b.bounds.center(), split_axis))
+        });
+
+        self.nodes.push(Node::Interior {
+            bounds,
+            second_child: 0,
+        });
+        let (first, second) = lights.split_at_mut(middle);
+        self.build(first, index);
+        let second_child = self.build(second, index);
+        self.nodes[index] = Node::Interior {
+            bounds,
+            second_child,
+        };
+        index
+    }
+
+    /// Probability of taking the first child of an interior node.
+    fn first_probability(
+        &self,
+        node: usize,
+        second_child: usize,
+        p: Point3,
+        n: Vec3,
+    ) -> Option<f64> {
+        let first = self.nodes[node + 1].bounds().importance(p, n);
+        let second = self.nodes[second_child].bounds().importance(p, n);
+        if first + second <= 0.0 {
+            return None;
+        }
+        Some(first / (first + second))
+    }
+
+    fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        let (mut node, mut pmf, mut u) = (0, 1.0, u);
+        loop {
+            stats::increment(Counter::LightBvhNodes);
+            match self.nodes[node] {
+                Node::Leaf { bounds, light } => {
+                    return (bounds.importance(p, n) > 0.0).then_some((light, pmf));
+                }
+                Node::Interior { second_child, .. } => {
+                    let probability = self.first_probability(node, second_child, p, n)?;
+                    if u < probability {
+                        node += 1;
+                        pmf *= probability;
+                        u /= probability;
+                    } else {
+                        node = second_child;
+                        pmf *= 1.0 - probability;
+                        u = ((u - probability) / (1.0 - probability)).min(1.0 - f64::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Probability of `sample` picking `light`, walking up from its leaf
+    /// since the tree can be deeper than a fixed width path would hold.
+    fn pmf(&self, p: Point3, n: Vec3, light: usize) -> f64 {
+        let Some(&leaf) = self.leaves.get(&light) else {
+            return 0.0;
+        };
+        if self.nodes[leaf].bounds().importance(p, n) <= 0.0 {
+            return 0.0;
+        }
+        let (mut node, mut pmf) = (leaf, 1.0);
+        while node != 0 {
+            stats::increment(Counter::LightBvhNodes);
+            let parent = self.parents[node];
+            let Node::Interior { second_child, .. } = self.nodes[parent] else {
+                unreachable!("leaves have no children");
+            };
+            let Some(probability) = self.first_probability(parent, second_child, p, n) else {
+                return 0.0;
+            };
+            pmf *= if node == parent + 1 {
+                probability
+            } else {
+                1.0 - probability
+            };
+            node = parent;
+        }
+        pmf
+    }
+}
+
+enum Picker {
+    Empty,
+    Uniform {
+        count: usize,
+    },
+    /// Over all lights, the ones picked separately have no power here.
+    Power(Distribution1D),
+    Bvh(LightBvh),
+}
+
+impl Picker {
+    fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        match self {
+            Picker::Empty => None,
+            Picker::Uniform { count } => {
+                let light = ((u * *count as f64) as usize).min(count - 1);
+                Some((light, 1.0 / *count as f64))
+            }
+            Picker::Power(distribution) => Some(distribution.sample_discrete(u)),
+            Picker::Bvh(bvh) => bvh.sample(p, n, u),
+        }
+    }
+
+    fn pmf(&self, p: Point3, n: Vec3, light: usize) -> f64 {
+        match self {
+            Picker::Empty => 0.0,
+            Picker::Uniform { count } => 1.0 / *count as f64,
+            Picker::Power(distribution) => distribution.pmf(light),
+            Picker::Bvh(bvh) => bvh.pmf(p, n, light),
+        }
+    }
+}
+
+/// Picks one light to send a shadow ray to from a shading point.
+pub struct LightSampler {
+    /// Lights without bounds, such as directional lights, which cannot be
+    /// ranked against the others. Each is picked as often as the whole
+    /// `picker`.
+    infinite: Vec<usize>,
+    picker: Picker,
+}
+
+impl LightSampler {
+    pub fn new(lights: &[Light], strategy: LightSampling) -> LightSampler {
+        if lights.is_empty() {
+            return LightSampler {
+                infinite: Vec::new(),
+                picker: Picker::Empty,
+            };
+        }
+        if strategy == LightSampling::Uniform {
+            return LightSampler {
+                infinite: Vec::new(),
+                picker: Picker::Uniform {
+                    count: lights.len(),
+                },
+            };
+        }
+
+        let bounds: Vec<Option<LightBounds>> = lights.iter().map(Light::bounds).collect();
+        let infinite = (0..lights.len()).filter(|i| bounds[*i].is_none()).collect();
+        let picker = match strategy {
+            LightSampling::Power => {
+                let power: Vec<f64> = lights
+                    .iter()
+                    .zip(&bounds)
+                    .map(|(light, bounds)| bounds.map_or(0.0, |_| light.power()))
+                    .collect();
+                if power.iter().any(|power| *power > 0.0) {
+                    Picker::Power(Distribution1D::new(&power))
+                } else {
+                    Picker::Empty
+                }
+            }
+            _ => {
+                let bounded: Vec<(usize, LightBounds)> = bounds
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(i, bounds)| Some((i, bounds?)))
+                    .filter(|(_, bounds)| bounds.phi > 0.0)
+                    .collect();
+                if bounded.is_empty() {
+                    Picker::Empty
+                } else {
+                    Picker::Bvh(LightBvh::new(bounded))
+                }
+            }
+        };
+        LightSampler { infinite, picker }
+    }
+
+    fn choices(&self) -> usize {
+        self.infinite.len() + !matches!(self.picker, Picker::Empty) as usize
+    }
+
+    /// Picks a light for a surface at `p` facing `n` with the uniform
+    /// random number `u`, returning its index and the probability of
+    /// picking it. `None` when no light can reach `p`.
+    pub fn sample(&self, p: Point3, n: Vec3, u: f64) -> Option<(usize, f64)> {
+        let choices = self.choices();
+        if choices == 0 {
+            return None;
+        }
+        let infinite_probability = self.infinite.len() as f64 / choices as f64;
+        if u < infinite_probability {
+            let i = ((u * choices as f64) as usize).min(self.infinite.len() - 1);
+            return Some((self.infinite[i], 1.0 / choices as f64));
+        }
+        let u = ((u - infinite_probability) / (1.0 - infinite_probability)).min(1.0 - f64::EPSILON);
+        let (light, pmf) = self.picker.sample(p, n, u)?;
+        Some((light, pmf / choices as f64))
+    }
+
+    /// Probability of `sample` picking `light` for a surface at `p` facing
+    /// `n`.
+    pub fn pmf(&self, p: Point3, n: Vec3, light: usize) -> f64 {
+        let choices = self.choices();
+        if self.infinite.contains(&light) {
+            return 1.0 / choices as f64;
+        }
+        self.picker.pmf(p, n, light) / choices as f64
+    }
+}
+
+#[test]
+fn light_sampler_pmf_test() {
+    use crate::shape::Shape;
+    use crate::vector::Color;
+
+    let mut lights = vec![
+        Light::Directional {
+            direction: Vec3::new(0.0, 1.0, 0.0),
+            irradiance: Color::new(1.0, 1.0, 1.0),
+        },
+        Light::Point {
+            position: Point3::new(0.0, 3.0, 0.0),
+            intensity: Color::new(5.0, 5.0, 5.0),
+        },
+    ];
+    for i in 0..20 {
+        lights.push(Light::Area {
+            shape: Shape::Quad {
+                corner: Point3::new(i as f64 - 10.0, 2.0, 0.0),
+                u: Vec3::new(0.5, 0.0, 0.0),
+                v: Vec3::new(0.0, 0.0, 0.5),
+            },
+            radiance: Color::new(1.0, 1.0, i as f64),
+            object_id: i,
+        });
+    }
+    let (p, n) = (Point3::new(1.0, 0.0, 0.3), Vec3::new(0.0, 1.0, 0.0));
+
+    for strategy in [
+        LightSampling::Uniform,
+        LightSampling::Power,
+        LightSampling::Bvh,
+    ] {
+        let sampler = LightSampler::new(&lights, strategy);
+        let total: f64 = (0..lights.len()).map(|i| sampler.pmf(p, n, i)).sum();
+        assert!(
+            (total - 1.0).abs() < 1e-9,
+            "{:?} sums to {}",
+            strategy,
+            total
+        );
+
+        for i in 0..200 {
+            let (light, pmf) = sampler.sample(p, n, i as f64 / 200.0).unwrap();
+            assert!((pmf - sampler.pmf(p, n, light)).abs() < 1e-12);
+        }
+    }
+
+    // Behind the panels, which face down, the BVH never picks them
+    let bvh = LightSampler::new(&lights, LightSampling::Bvh);
+    let down = Point3::new(0.0, 5.0, 0.0);
+    assert_eq!(0.0, bvh.pmf(down, n, 10));
+}
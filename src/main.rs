@@ -1,41 +1,116 @@
+mod aov;
+mod aperture;
+mod blackbody;
+mod bounds;
+#[cfg(test)]
+mod bsdftest;
 mod camera;
+mod debug;
+mod denoise;
+mod distribution;
+mod environment;
+mod exr;
+mod film;
+mod frame;
+mod framebuffer;
+mod hdr;
 mod hittable;
+mod ies;
+mod lens;
+mod light;
+mod lightsampler;
 mod material;
+mod microfacet;
+mod options;
+mod pathdebug;
+mod principled;
+mod progress;
 mod ray;
+mod shape;
+mod sky;
+mod stats;
+mod tonemap;
 mod util;
 mod vector;
 mod world;
 
+use crate::aov::{Aov, AovBuffers};
+use crate::aperture::{Aperture, ApertureImage};
+use crate::environment::{Environment, EnvironmentMap};
+use crate::film::{Film, Filter};
 use crate::hittable::HittableList;
+use crate::light::Light;
 use vector::{Color, Point3, Vec3};
 
-use crate::ray::ray_color;
+use crate::options::RenderOptions;
+use crate::ray::render_sample;
+use crate::sky::Sky;
+use crate::stats::{Counter, RenderStats};
+use crate::world::Scene;
 use camera::Camera;
 use std::env;
-use std::ops::Div;
-use std::time::Instant;
-
-static SAMPLES_PER_PIXEL: i32 = 10;
-static MAX_DEPTH: i32 = 50;
+use std::io::{self, BufWriter};
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let options = RenderOptions::from_args(&args).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(1);
+    });
+
+    if let Some(seed) = options.seed {
+        util::seed(seed);
+    }
+
     // World
-    let world: HittableList;
-    if args.len() >= 2 && &args[1] == "random" {
-        world = world::random_scene();
+    let (world, lights): (HittableList, Vec<Light>) = if options.random_scene {
+        world::random_scene(options.string_lights)
     } else {
-        world = world::load_world_file().unwrap();
-    }
+        world::load_world_file().unwrap()
+    };
+    let environment = match (&options.environment_map, &options.sky) {
+        (Some(path), _) => EnvironmentMap::load(
+            path,
+            options.environment_rotation,
+            options.environment_intensity,
+        )
+        .map(Environment::Map)
+        .unwrap_or_else(|err| {
+            eprintln!("Could not load environment map '{}': {}", path, err);
+            process::exit(1);
+        }),
+        (None, Some(settings)) => {
+            Environment::Sky(Box::new(Sky::new(settings, options.environment_intensity)))
+        }
+        (None, None) => Environment::Gradient {
+            intensity: options.environment_intensity,
+        },
+    };
+    let scene = Scene::new(world, environment, lights, options.light_sampling);
 
     // Image
-    let aspect_ratio = 3.0 / 2.0;
+    let aspect_ratio = options.projection.aspect_ratio().unwrap_or(3.0 / 2.0);
     let image_width = 1200;
     let image_height = (image_width as f64 / aspect_ratio) as i32;
 
     let lookfrom = Point3::new(13.0, 2.0, 3.0);
     let lookat = Point3::new(0.0, 0.0, 0.0);
     let dist_to_focus = 10.0;
+    let aperture = match (options.aperture_blades, &options.aperture_image) {
+        (Some(blades), _) => Aperture::Polygon {
+            blades,
+            rotation: options.blade_rotation,
+        },
+        (None, Some(path)) => hdr::load_hdr(path)
+            .and_then(|image| ApertureImage::new(&image))
+            .map(Aperture::Image)
+            .unwrap_or_else(|err| {
+                eprintln!("Could not load aperture image '{}': {}", path, err);
+                process::exit(1);
+            }),
+        (None, None) => Aperture::Circle,
+    };
     let camera = Camera::new(
         lookfrom,
         lookat,
@@ -44,50 +119,169 @@ fn main() {
         aspect_ratio,
         0.1,
         dist_to_focus,
+    )
+    .with_sensor(options.sensor_diagonal)
+    .with_shift(options.shift.0, options.shift.1)
+    .with_tilt(options.tilt)
+    .with_projection(options.projection)
+    .with_aperture(aperture)
+    .with_cats_eye(options.cats_eye);
+    let camera = match options.exposure {
+        Some(exposure) => camera.with_exposure(exposure),
+        None => camera,
+    };
+    let camera = match &options.lens {
+        Some(path) => camera.with_lens(
+            lens::load_lens(path, options.sensor_diagonal).unwrap_or_else(|err| {
+                eprintln!("Could not load lens '{}': {}", path, err);
+                process::exit(1);
+            }),
+        ),
+        None => camera,
+    };
+
+    // Options only allow a traced pixel together with a seed
+    if let (Some((x, y)), Some(seed)) = (options.trace_pixel, options.seed) {
+        if x >= image_width as usize || y >= image_height as usize {
+            eprintln!(
+                "Pixel ({}, {}) is outside the {}x{} image",
+                x, y, image_width, image_height
+            );
+            process::exit(1);
+        }
+        let trace = pathdebug::trace_pixel(
+            &camera,
+            &scene,
+            &options,
+            (image_width, image_height),
+            (x, y),
+            seed,
+        );
+        print!("{}", trace);
+        return;
+    }
+
+    // Debug values are kept per pixel instead of being blurred across edges
+    let filter = match options.debug_mode {
+        Some(_) => Filter::Box,
+        None => options.filter,
+    };
+    let filter_radius = match options.debug_mode {
+        Some(_) => filter.default_radius(),
+        None => options.filter_radius.unwrap_or(filter.default_radius()),
+    };
+    let mut film = Film::new(
+        image_width as usize,
+        image_height as usize,
+        filter,
+        filter_radius,
     );
+    // The denoiser is guided by the albedo and normal passes
+    let mut collected_aovs = options.aovs.clone();
+    if options.denoise.is_some() {
+        for aov in [Aov::Albedo, Aov::Normal] {
+            if !collected_aovs.contains(&aov) {
+                collected_aovs.push(aov);
+            }
+        }
+    }
+    let mut aov_buffers =
+        AovBuffers::new(image_width as usize, image_height as usize, &collected_aovs);
 
-    println!("P3");
-    println!("{} {}", image_width, image_height);
-    println!("255");
+    let mut progress = options.progress.reporter();
+    let total_samples = (image_width * image_height * options.samples_per_pixel) as u64;
+    let mut samples_done: u64 = 0;
+    progress.start(total_samples);
 
-    let start = Instant::now();
-    // Pixels are written from left to right, top to bottom
+    // Pixels are rendered from left to right, top to bottom
     let mut row = image_height - 1;
     while row >= 0 {
-        // eprintln!("Scanlines remaining: {}", row);
         for col in 0..image_width {
-            let mut pixel_color = Color::default();
+            if let Some(seed) = options.seed {
+                let y = (image_height - 1 - row) as usize;
+                util::seed(util::pixel_seed(seed, col as usize, y));
+            }
+
+            for _ in 0..options.samples_per_pixel {
+                if let Some(mode) = options.debug_mode {
+                    let (x, film_y, new_ray) =
+                        camera.sample_pixel(col, row, image_width, image_height);
+                    let color = match new_ray {
+                        Some((new_ray, _)) => {
+                            stats::increment(Counter::PrimaryRays);
+                            mode.shade(new_ray, &scene, &options.path)
+                        }
+                        None => Color::default(),
+                    };
+                    film.add_sample(x, film_y, color);
+                    continue;
+                }
 
-            for _ in 0..SAMPLES_PER_PIXEL {
-                let u = (col as f64 + util::random_double()) / (image_width as f64 - 1.0);
-                let v = (row as f64 + util::random_double()) / (image_height as f64 - 1.0);
-                let new_ray = camera.ray(u, v);
-                pixel_color = pixel_color + ray_color(new_ray, &world, MAX_DEPTH);
+                let (x, film_y, traced) = render_sample(
+                    &camera,
+                    &scene,
+                    &options.path,
+                    (col, row),
+                    (image_width, image_height),
+                    None,
+                );
+                let Some((new_ray, weight, sample)) = traced else {
+                    film.add_sample(x, film_y, Color::default());
+                    continue;
+                };
+                stats::increment(Counter::PrimaryRays);
+                film.add_sample(x, film_y, camera.exposure_scale() * weight * sample.color);
+                aov_buffers.add_sample(x, film_y, &sample, &new_ray);
             }
-            write_color(pixel_color, SAMPLES_PER_PIXEL);
         }
+        samples_done += (image_width * options.samples_per_pixel) as u64;
+        progress.update(samples_done, stats::get(Counter::RaysTraced));
         row -= 1;
     }
 
-    let duration = start.elapsed().div(60);
-    eprintln!("Time elapsed is: {:.2?} minutes", duration);
-}
+    progress.finish(samples_done, stats::get(Counter::RaysTraced));
 
-fn write_color(pixel_color: Color, samples_per_pixel: i32) {
-    let mut ir = pixel_color.x;
-    let mut ig = pixel_color.y;
-    let mut ib = pixel_color.z;
-
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0.
-    let scale = 1.0 / samples_per_pixel as f64;
-    ir = f64::sqrt(ir * scale);
-    ig = f64::sqrt(ig * scale);
-    ib = f64::sqrt(ib * scale);
-
-    println!(
-        "{} {} {}",
-        (256.0 * util::clamp(ir, 0.0, 0.999)) as i32,
-        (256.0 * util::clamp(ig, 0.0, 0.999)) as i32,
-        (256.0 * util::clamp(ib, 0.0, 0.999)) as i32
-    );
+    let mut framebuffer = film.resolve();
+    if let Some(mode) = options.debug_mode {
+        if mode.is_heatmap() {
+            debug::apply_heatmap(&mut framebuffer);
+        }
+    } else if let Some(settings) = &options.denoise {
+        framebuffer = denoise::denoise(
+            &framebuffer,
+            &aov_buffers.resolve(Aov::Albedo).unwrap(),
+            &aov_buffers.resolve(Aov::Normal).unwrap(),
+            settings,
+        );
+    }
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match options.debug_mode {
+        Some(_) => framebuffer.write_linear_ppm(&mut out),
+        None => framebuffer.write_ppm(&mut out, &options.tone_mapper),
+    }
+    .unwrap();
+
+    if options.debug_mode.is_some() {
+        // Passes are not collected for debug renders
+    } else if let Some(path) = &options.exr_path {
+        aov_buffers
+            .write_exr(path, &framebuffer, &options.aovs)
+            .unwrap_or_else(|err| {
+                eprintln!("Could not write EXR file '{}': {}", path, err);
+                process::exit(1);
+            });
+    } else if !options.aovs.is_empty() {
+        aov_buffers
+            .write_pfm_files(&options.aov_prefix, &options.aovs)
+            .unwrap_or_else(|err| {
+                eprintln!(
+                    "Could not write passes with prefix '{}': {}",
+                    options.aov_prefix, err
+                );
+                process::exit(1);
+            });
+    }
+    RenderStats::snapshot().print(options.stats);
 }
@@ -1,15 +1,99 @@
+use crate::frame::{sample_cosine_hemisphere, Frame};
 use crate::hittable::HitRecord;
+use crate::microfacet::{self, Ggx};
+use crate::principled::{Lobe, Principled};
 use crate::ray::Ray;
-use crate::util::random_double;
+use crate::util::{random_double, PI};
 use crate::vector::{Color, Vec3};
 
 #[derive(Clone, Copy)]
 pub enum MaterialEnum {
-    Lambertian { albedo: Color },
-    Metal { albedo: Color, fuzziness: f64 },
-    Dielectric { index_of_refraction: f64 },
+    Lambertian {
+        albedo: Color,
+    },
+    /// GGX microfacet conductor with complex index of refraction `eta + i k`.
+    /// Anisotropy stretches the highlight along the surface tangent.
+    Metal {
+        eta: Color,
+        k: Color,
+        roughness: f64,
+        anisotropy: f64,
+    },
+    Dielectric {
+        index_of_refraction: f64,
+    },
+    /// Frosted glass: a GGX microfacet interface with exact Fresnel. Light
+    /// travelling inside is attenuated by `exp(-absorption * distance)`.
+    RoughDielectric {
+        index_of_refraction: f64,
+        roughness: f64,
+        absorption: Color,
+    },
+    Principled(Principled),
+    /// Light source that sends out `radiance` from its front side and
+    /// absorbs all light that reaches it.
+    Emissive {
+        radiance: Color,
+    },
 }
 
+/// Lobe a scattered ray was drawn from, used for per-lobe depth limits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScatterKind {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+/// Describes the lobe a direction was sampled from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScatterFlags {
+    pub kind: ScatterKind,
+    /// Sampled from a delta distribution such as a perfect mirror. `eval`
+    /// and `pdf` are zero for these directions, and `value` and `pdf` of
+    /// the sample only have a meaning as a ratio.
+    pub is_delta: bool,
+}
+
+/// Direction sampled by `MaterialEnum::sample` in the local shading frame.
+#[derive(Clone, Copy, Debug)]
+pub struct BsdfSample {
+    pub wi: Vec3,
+    /// BSDF value for the pair of directions.
+    pub value: Color,
+    pub pdf: f64,
+    pub flags: ScatterFlags,
+}
+
+impl BsdfSample {
+    /// Factor the path throughput is multiplied by, `value * cos / pdf`.
+    pub fn weight(&self) -> Color {
+        self.value * (self.wi.z.abs() / self.pdf)
+    }
+
+    fn delta(wi: Vec3, probability: f64, reflectance: Color, kind: ScatterKind) -> BsdfSample {
+        BsdfSample {
+            wi,
+            value: reflectance * (probability / wi.z.abs()),
+            pdf: probability,
+            flags: ScatterFlags {
+                kind,
+                is_delta: true,
+            },
+        }
+    }
+}
+
+/// Names of the material variants, indexed by `MaterialEnum::kind`.
+pub const MATERIAL_NAMES: [&str; 6] = [
+    "lambertian",
+    "metal",
+    "dielectric",
+    "rough_dielectric",
+    "principled",
+    "emissive",
+];
+
 impl Default for MaterialEnum {
     fn default() -> Self {
         MaterialEnum::Lambertian {
@@ -18,68 +102,363 @@ impl Default for MaterialEnum {
     }
 }
 
+/// Measured complex IOR at roughly 650, 550 and 450 nm.
+static CONDUCTOR_PRESETS: [(&str, [f64; 3], [f64; 3]); 4] = [
+    ("gold", [0.143, 0.374, 1.442], [3.983, 2.385, 1.603]),
+    ("copper", [0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+    ("aluminium", [1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+    ("silver", [0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+];
+
 impl MaterialEnum {
-    pub fn scatter(
-        &self,
-        r_in: &Ray,
-        hit_record: &HitRecord,
-        attenuation: &mut Color,
-        scattered: &mut Ray,
-    ) -> bool {
+    /// Conductor whose reflectance at normal incidence is `albedo`.
+    pub fn metal(albedo: Color, roughness: f64, anisotropy: f64) -> MaterialEnum {
+        let (eta, k) = microfacet::conductor_from_reflectance(albedo, albedo);
+        MaterialEnum::Metal {
+            eta,
+            k,
+            roughness,
+            anisotropy,
+        }
+    }
+
+    /// Conductor with the measured IOR of `gold`, `copper`, `aluminium` or
+    /// `silver`.
+    pub fn metal_preset(name: &str, roughness: f64, anisotropy: f64) -> Option<MaterialEnum> {
+        let (_, eta, k) = CONDUCTOR_PRESETS
+            .iter()
+            .find(|(preset, _, _)| *preset == name)?;
+        Some(MaterialEnum::Metal {
+            eta: Color::new(eta[0], eta[1], eta[2]),
+            k: Color::new(k[0], k[1], k[2]),
+            roughness,
+            anisotropy,
+        })
+    }
+
+    /// The same material with its glossy and refractive lobes at least as
+    /// rough as `roughness`. Sharp reflections and refractions reached after
+    /// a diffuse bounce are what produce caustic fireflies, roughening the
+    /// whole material keeps `eval`, `pdf` and `sample` consistent while it
+    /// trades a little bias for a much faster converging estimate.
+    pub fn regularized(&self, roughness: f64) -> MaterialEnum {
+        match *self {
+            MaterialEnum::Metal {
+                eta,
+                k,
+                roughness: own,
+                anisotropy,
+            } => MaterialEnum::Metal {
+                eta,
+                k,
+                roughness: own.max(roughness),
+                anisotropy,
+            },
+            MaterialEnum::Dielectric {
+                index_of_refraction,
+            } => MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness,
+                absorption: Color::default(),
+            },
+            MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness: own,
+                absorption,
+            } => MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness: own.max(roughness),
+                absorption,
+            },
+            MaterialEnum::Principled(principled) => MaterialEnum::Principled(Principled {
+                roughness: principled.roughness.max(roughness),
+                ..principled
+            }),
+            MaterialEnum::Lambertian { .. } | MaterialEnum::Emissive { .. } => *self,
+        }
+    }
+
+    pub fn kind(&self) -> usize {
+        match self {
+            MaterialEnum::Lambertian { .. } => 0,
+            MaterialEnum::Metal { .. } => 1,
+            MaterialEnum::Dielectric { .. } => 2,
+            MaterialEnum::RoughDielectric { .. } => 3,
+            MaterialEnum::Principled(_) => 4,
+            MaterialEnum::Emissive { .. } => 5,
+        }
+    }
+
+    /// Colour of the surface, used as a guide by the denoiser.
+    pub fn albedo(&self) -> Color {
+        match self {
+            MaterialEnum::Lambertian { albedo } => *albedo,
+            MaterialEnum::Metal { eta, k, .. } => microfacet::fresnel_conductor(1.0, *eta, *k),
+            MaterialEnum::Dielectric { .. }
+            | MaterialEnum::RoughDielectric { .. }
+            | MaterialEnum::Emissive { .. } => Color::new(1.0, 1.0, 1.0),
+            MaterialEnum::Principled(principled) => principled.base_color,
+        }
+    }
+
+    /// Radiance sent out from the hit towards the ray that found it.
+    pub fn emitted(&self, hit_record: &HitRecord) -> Color {
         match self {
-            MaterialEnum::Lambertian { albedo: albedo_val } => {
-                let mut scatter_direction = hit_record.normal + Vec3::random_unit_vector();
+            MaterialEnum::Emissive { radiance } if hit_record.is_front_face => *radiance,
+            _ => Color::default(),
+        }
+    }
+
+    /// Fraction of light that survives travelling along `ray` to the hit,
+    /// which is inside the object when the back face was hit.
+    pub fn transmittance(&self, ray: &Ray, hit_record: &HitRecord) -> Color {
+        match self {
+            MaterialEnum::RoughDielectric { absorption, .. } if !hit_record.is_front_face => {
+                let distance = hit_record.t * ray.dir.length();
+                Color::new(
+                    (-absorption.x * distance).exp(),
+                    (-absorption.y * distance).exp(),
+                    (-absorption.z * distance).exp(),
+                )
+            }
+            _ => Color::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Samples a scattered ray at the hit, together with the BSDF sample in
+    /// the local frame. `None` when the path is absorbed.
+    pub fn scatter(&self, r_in: &Ray, hit_record: &HitRecord) -> Option<(Ray, BsdfSample)> {
+        let frame = shading_frame(hit_record);
+        let wo = frame.to_local(-r_in.dir.unit_vector());
+        let u = [random_double(), random_double(), random_double()];
+        let sample = self.sample(wo, u)?;
+        Some((Ray::new(hit_record.p, frame.to_world(sample.wi)), sample))
+    }
+
+    /// BSDF value for light arriving from `wi` and leaving towards `wo`.
+    ///
+    /// Directions are unit vectors in the local frame of `shading_frame`, so
+    /// `wo.z < 0.0` means the surface is seen from inside. Delta lobes are
+    /// not included.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        match self {
+            MaterialEnum::Lambertian { albedo } => {
+                if wo.z * wi.z > 0.0 {
+                    *albedo / PI
+                } else {
+                    Color::default()
+                }
+            }
+            MaterialEnum::Metal {
+                eta,
+                k,
+                roughness,
+                anisotropy,
+            } => {
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+                let (wo, wi) = two_sided(wo, wi);
+                if ggx.is_smooth() || wi.z <= 0.0 {
+                    return Color::default();
+                }
+                let wm = (wo + wi).unit_vector();
+                ggx.eval_reflection(wo, wi) * microfacet::fresnel_conductor(wo.dot(wm), *eta, *k)
+            }
+            MaterialEnum::Dielectric { .. } | MaterialEnum::Emissive { .. } => Color::default(),
+            MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness,
+                ..
+            } => {
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
+                if ggx.is_smooth() {
+                    return Color::default();
+                }
+                let (wo, wi, eta) = oriented(wo, wi, *index_of_refraction);
+                let value = ggx.eval_dielectric(eta, wo, wi);
+                Color::new(value, value, value)
+            }
+            MaterialEnum::Principled(principled) => principled.eval(wo, wi),
+        }
+    }
 
-                // Catch degenerate scatter direction
-                if scatter_direction.near_zero() {
-                    scatter_direction = hit_record.normal;
+    /// Density of `sample` returning `wi`, over solid angle. Zero for delta
+    /// lobes.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        match self {
+            MaterialEnum::Lambertian { .. } => {
+                if wo.z * wi.z > 0.0 {
+                    wi.z.abs() / PI
+                } else {
+                    0.0
                 }
+            }
+            MaterialEnum::Metal {
+                roughness,
+                anisotropy,
+                ..
+            } => {
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+                if ggx.is_smooth() {
+                    return 0.0;
+                }
+                let (wo, wi) = two_sided(wo, wi);
+                ggx.reflection_pdf(wo, wi)
+            }
+            MaterialEnum::Dielectric { .. } | MaterialEnum::Emissive { .. } => 0.0,
+            MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness,
+                ..
+            } => {
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
+                if ggx.is_smooth() {
+                    return 0.0;
+                }
+                let (wo, wi, eta) = oriented(wo, wi, *index_of_refraction);
+                ggx.dielectric_pdf(eta, wo, wi)
+            }
+            MaterialEnum::Principled(principled) => principled.pdf(wo, wi),
+        }
+    }
 
-                *scattered = Ray::new(hit_record.p, scatter_direction);
-                *attenuation = *albedo_val;
-                true
+    /// Samples an incoming direction for light leaving towards `wo`, using
+    /// the uniform random numbers `u`. `None` when the path is absorbed.
+    pub fn sample(&self, wo: Vec3, u: [f64; 3]) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        match self {
+            MaterialEnum::Lambertian { .. } => {
+                let wi = sample_cosine_hemisphere(u[1], u[2]);
+                let wi = Vec3::new(wi.x, wi.y, wi.z.copysign(wo.z));
+                self.continuous_sample(wo, wi, ScatterKind::Diffuse)
             }
             MaterialEnum::Metal {
-                albedo: albedo_val,
-                fuzziness: f,
+                eta,
+                k,
+                roughness,
+                anisotropy,
             } => {
-                let reflected = Vec3::reflect(r_in.dir.unit_vector(), hit_record.normal);
-                *scattered = Ray::new(hit_record.p, reflected + *f * Vec3::random_in_unit_sphere());
-                *attenuation = *albedo_val;
+                let ggx = Ggx::from_roughness(*roughness, *anisotropy);
+                let flip = wo.z.signum();
+                let oriented_wo = Vec3::new(wo.x, wo.y, wo.z.abs());
+                if ggx.is_smooth() {
+                    let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                    let reflectance = microfacet::fresnel_conductor(wo.z.abs(), *eta, *k);
+                    return Some(BsdfSample::delta(
+                        wi,
+                        1.0,
+                        reflectance,
+                        ScatterKind::Specular,
+                    ));
+                }
 
-                return scattered.dir.dot(hit_record.normal) > 0.0;
+                let wi = ggx.sample_reflection(oriented_wo, u[1], u[2])?;
+                let wi = Vec3::new(wi.x, wi.y, wi.z * flip);
+                self.continuous_sample(wo, wi, ScatterKind::Specular)
             }
             MaterialEnum::Dielectric {
-                index_of_refraction: index_of_ref,
+                index_of_refraction,
             } => {
-                *attenuation = Color::new(1.0, 1.0, 1.0);
-                let refraction_ratio;
-                if hit_record.is_front_face {
-                    refraction_ratio = 1.0 / index_of_ref;
+                let refraction_ratio = if wo.z > 0.0 {
+                    1.0 / index_of_refraction
                 } else {
-                    refraction_ratio = *index_of_ref;
-                }
-
-                let unit_direction = r_in.dir.unit_vector();
-                let cos_theta = -unit_direction.dot(hit_record.normal).min(1.0);
+                    *index_of_refraction
+                };
+                let cos_theta = wo.z.abs().min(1.0);
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
-                let cannot_refract = (refraction_ratio * sin_theta) > 1.0;
-
-                let direction;
-                if cannot_refract || self.reflectance(cos_theta, refraction_ratio) > random_double()
-                {
-                    direction = Vec3::reflect(unit_direction, hit_record.normal);
+                let reflectance = if refraction_ratio * sin_theta > 1.0 {
+                    1.0
+                } else {
+                    self.reflectance(cos_theta, refraction_ratio)
+                };
+                let normal = Vec3::new(0.0, 0.0, wo.z.signum());
+                let white = Color::new(1.0, 1.0, 1.0);
+                if u[0] < reflectance {
+                    let wi = Vec3::new(-wo.x, -wo.y, wo.z);
+                    Some(BsdfSample::delta(
+                        wi,
+                        reflectance,
+                        white,
+                        ScatterKind::Specular,
+                    ))
                 } else {
-                    direction = Vec3::refract(unit_direction, hit_record.normal, refraction_ratio);
+                    let wi = microfacet::refract(wo, normal, 1.0 / refraction_ratio)?;
+                    Some(BsdfSample::delta(
+                        wi,
+                        1.0 - reflectance,
+                        white,
+                        ScatterKind::Transmission,
+                    ))
+                }
+            }
+            MaterialEnum::RoughDielectric {
+                index_of_refraction,
+                roughness,
+                ..
+            } => {
+                let ggx = Ggx::from_roughness(*roughness, 0.0);
+                let flip = wo.z.signum();
+                let (oriented_wo, _, eta) = oriented(wo, wo, *index_of_refraction);
+                if ggx.is_smooth() {
+                    let normal = Vec3::new(0.0, 0.0, 1.0);
+                    let reflectance = microfacet::fresnel_dielectric(oriented_wo.z, eta);
+                    let white = Color::new(1.0, 1.0, 1.0);
+                    let sample = if u[0] < reflectance {
+                        let wi = microfacet::reflect(oriented_wo, normal);
+                        BsdfSample::delta(wi, reflectance, white, ScatterKind::Specular)
+                    } else {
+                        let wi = microfacet::refract(oriented_wo, normal, eta)?;
+                        BsdfSample::delta(wi, 1.0 - reflectance, white, ScatterKind::Transmission)
+                    };
+                    let wi = sample.wi;
+                    return Some(BsdfSample {
+                        wi: Vec3::new(wi.x, wi.y, wi.z * flip),
+                        ..sample
+                    });
                 }
 
-                *scattered = Ray::new(hit_record.p, direction);
-                return true;
+                let wi = ggx.sample_dielectric(eta, oriented_wo, u)?;
+                let kind = if wi.z < 0.0 {
+                    ScatterKind::Transmission
+                } else {
+                    ScatterKind::Specular
+                };
+                self.continuous_sample(wo, Vec3::new(wi.x, wi.y, wi.z * flip), kind)
             }
+            MaterialEnum::Principled(principled) => {
+                let (wi, lobe) = principled.sample(wo, u)?;
+                let kind = match lobe {
+                    Lobe::Diffuse => ScatterKind::Diffuse,
+                    Lobe::Glass if wi.z * wo.z < 0.0 => ScatterKind::Transmission,
+                    Lobe::Specular | Lobe::Clearcoat | Lobe::Glass => ScatterKind::Specular,
+                };
+                self.continuous_sample(wo, wi, kind)
+            }
+            MaterialEnum::Emissive { .. } => None,
         }
     }
 
+    /// Completes a direction drawn from a continuous distribution with the
+    /// value and density of the whole material, so that lobes which could
+    /// also have produced it are accounted for.
+    fn continuous_sample(&self, wo: Vec3, wi: Vec3, kind: ScatterKind) -> Option<BsdfSample> {
+        let pdf = self.pdf(wo, wi);
+        if pdf <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            value: self.eval(wo, wi),
+            pdf,
+            flags: ScatterFlags {
+                kind,
+                is_delta: false,
+            },
+        })
+    }
+
     pub fn reflectance(&self, cosine: f64, ref_idx: f64) -> f64 {
         match self {
             MaterialEnum::Dielectric {
@@ -88,11 +467,41 @@ impl MaterialEnum {
                 // Use Schlick's approximation for reflectance.
                 let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
                 r0 = r0 * r0;
-                return r0 + (1.0 - r0) * (1.0 - cosine).powi(5);
-            }
-            _ => {
-                return 0.0;
+                r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
             }
+            _ => 0.0,
         }
     }
 }
+
+/// Local frame around the normal pointing out of the object, so that the
+/// sign of `wo.z` tells the inside from the outside.
+pub fn shading_frame(hit_record: &HitRecord) -> Frame {
+    if hit_record.is_front_face {
+        Frame::from_normal(hit_record.normal)
+    } else {
+        Frame::from_normal(-hit_record.normal)
+    }
+}
+
+/// Mirrors both directions below the surface when `wo` is, for materials
+/// that look the same from both sides.
+fn two_sided(wo: Vec3, wi: Vec3) -> (Vec3, Vec3) {
+    if wo.z < 0.0 {
+        let flip = Vec3::new(1.0, 1.0, -1.0);
+        (wo * flip, wi * flip)
+    } else {
+        (wo, wi)
+    }
+}
+
+/// Flips both directions so that `wo` is above the surface and returns the
+/// index of refraction on the far side relative to the side of `wo`.
+fn oriented(wo: Vec3, wi: Vec3, index_of_refraction: f64) -> (Vec3, Vec3, f64) {
+    if wo.z < 0.0 {
+        let flip = Vec3::new(1.0, 1.0, -1.0);
+        (wo * flip, wi * flip, 1.0 / index_of_refraction)
+    } else {
+        (wo, wi, index_of_refraction)
+    }
+}
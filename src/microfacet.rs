@@ -0,0 +1,323 @@
+use crate::util::PI;
+use crate::vector::{Color, Vec3};
+
+/// Anisotropic GGX (Trowbridge-Reitz) microfacet distribution with Smith
+/// masking-shadowing. All directions are in the local shading frame, where
+/// the macro surface normal is +Z.
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+/// Below this roughness the surface is treated as a perfect mirror.
+static SMOOTH_ALPHA: f64 = 1e-3;
+
+impl Ggx {
+    /// Maps perceptual roughness to `alpha = roughness^2` and stretches it
+    /// along the tangent by `anisotropy` in 0.0..=1.0.
+    pub fn from_roughness(roughness: f64, anisotropy: f64) -> Ggx {
+        let alpha = roughness * roughness;
+        let aspect = f64::sqrt(1.0 - 0.9 * anisotropy);
+        Ggx {
+            alpha_x: (alpha / aspect).max(SMOOTH_ALPHA * 0.1),
+            alpha_y: (alpha * aspect).max(SMOOTH_ALPHA * 0.1),
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    /// Distribution of microfacet normals `wm`.
+    pub fn d(&self, wm: Vec3) -> f64 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x / self.alpha_x;
+        let y = wm.y / self.alpha_y;
+        let denominator = x * x + y * y + wm.z * wm.z;
+        1.0 / (PI * self.alpha_x * self.alpha_y * denominator * denominator)
+    }
+
+    fn lambda(&self, w: Vec3) -> f64 {
+        if w.z == 0.0 {
+            return f64::INFINITY;
+        }
+        let x = self.alpha_x * w.x;
+        let y = self.alpha_y * w.y;
+        let tan2 = (x * x + y * y) / (w.z * w.z);
+        (-1.0 + f64::sqrt(1.0 + tan2)) / 2.0
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated masking-shadowing for the pair of directions.
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal from the distribution of normals visible
+    /// from `wo` (Heitz 2018). `wo` must be in the upper hemisphere.
+    pub fn sample_visible_normal(&self, wo: Vec3, u1: f64, u2: f64) -> Vec3 {
+        // Stretch the view direction to the hemisphere configuration
+        let vh = Vec3::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).unit_vector();
+        let length_squared = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length_squared > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / length_squared.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(t1);
+
+        // Sample the projected area of the visible hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+        // Unstretch back to the ellipsoid configuration
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+
+    /// Density of `sample_visible_normal` returning `wm`.
+    pub fn visible_normal_pdf(&self, wo: Vec3, wm: Vec3) -> f64 {
+        if wo.z <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * wo.dot(wm).max(0.0) * self.d(wm) / wo.z
+    }
+
+    /// Microfacet reflection without the Fresnel factor, for `wo` and `wi`
+    /// both above the surface.
+    pub fn eval_reflection(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = (wo + wi).unit_vector();
+        self.d(wm) * self.g(wo, wi) / (4.0 * wo.z * wi.z)
+    }
+
+    /// Samples a reflected direction by reflecting `wo` about a visible
+    /// normal. `None` when it ends up below the surface.
+    pub fn sample_reflection(&self, wo: Vec3, u1: f64, u2: f64) -> Option<Vec3> {
+        let wm = self.sample_visible_normal(wo, u1, u2);
+        let wi = reflect(wo, wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(wi)
+    }
+
+    /// Density of `sample_reflection` returning `wi`.
+    pub fn reflection_pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let wm = (wo + wi).unit_vector();
+        self.visible_normal_pdf(wo, wm) / (4.0 * wo.dot(wm))
+    }
+
+    /// Rough dielectric interface with relative index of refraction `eta`,
+    /// seen from `wo` above the surface. Returns the BSDF value towards
+    /// `wi` on either side, with exact Fresnel splitting reflection from
+    /// refraction.
+    pub fn eval_dielectric(&self, eta: f64, wo: Vec3, wi: Vec3) -> f64 {
+        let wm = match half_vector(eta, wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let fresnel = fresnel_dielectric(wo.dot(wm), eta);
+        let microfacet = self.d(wm) * self.g(wo, wi);
+        if wi.z > 0.0 {
+            fresnel * microfacet / (4.0 * wo.z * wi.z)
+        } else {
+            let denominator = wi.dot(wm) + wo.dot(wm) / eta;
+            (1.0 - fresnel) * microfacet * (wi.dot(wm) * wo.dot(wm)).abs()
+                / (wi.z * wo.z * denominator * denominator).abs()
+        }
+    }
+
+    /// Samples a visible normal and then reflection or refraction through
+    /// it in proportion to Fresnel, choosing with `u[0]`.
+    pub fn sample_dielectric(&self, eta: f64, wo: Vec3, u: [f64; 3]) -> Option<Vec3> {
+        let wm = self.sample_visible_normal(wo, u[1], u[2]);
+        let wi = if u[0] < fresnel_dielectric(wo.dot(wm), eta) {
+            reflect(wo, wm)
+        } else {
+            refract(wo, wm, eta)?
+        };
+        // Rays that cross to the other side of the macro surface than the
+        // microfacet sends them have no valid half vector
+        let transmitted = wi.dot(wm) < 0.0;
+        if wi.z == 0.0 || transmitted != (wi.z < 0.0) {
+            return None;
+        }
+        Some(wi)
+    }
+
+    /// Density of `sample_dielectric` returning `wi`.
+    pub fn dielectric_pdf(&self, eta: f64, wo: Vec3, wi: Vec3) -> f64 {
+        let wm = match half_vector(eta, wo, wi) {
+            Some(wm) => wm,
+            None => return 0.0,
+        };
+        let fresnel = fresnel_dielectric(wo.dot(wm), eta);
+        let normal_pdf = self.visible_normal_pdf(wo, wm);
+        if wi.z > 0.0 {
+            fresnel * normal_pdf / (4.0 * wo.dot(wm))
+        } else {
+            let denominator = wi.dot(wm) + wo.dot(wm) / eta;
+            (1.0 - fresnel) * normal_pdf * wi.dot(wm).abs() / (denominator * denominator)
+        }
+    }
+}
+
+/// Generalised half vector of a reflection or refraction, facing up. `None`
+/// when no microfacet could connect the two directions.
+fn half_vector(eta: f64, wo: Vec3, wi: Vec3) -> Option<Vec3> {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return None;
+    }
+    let reflected = wi.z > 0.0;
+    let wm = if reflected { wo + wi } else { wo + eta * wi };
+    if wm.length_squared() == 0.0 {
+        return None;
+    }
+    let wm = wm.unit_vector();
+    let wm = if wm.z < 0.0 { -wm } else { wm };
+    if wo.dot(wm) <= 0.0 || (wi.dot(wm) < 0.0) == reflected {
+        return None;
+    }
+    Some(wm)
+}
+
+/// Mirrors `wo` about the normal `n`.
+pub fn reflect(wo: Vec3, n: Vec3) -> Vec3 {
+    -wo + 2.0 * wo.dot(n) * n
+}
+
+/// Refracts `wo` through the interface with normal `n` on its side, where
+/// `eta` is the ratio of the index of refraction below the surface to the
+/// one above. `None` on total internal reflection.
+pub fn refract(wo: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+    let cos_theta_i = wo.dot(n);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-wo / eta + (cos_theta_i / eta - cos_theta_t) * n)
+}
+
+/// Exact unpolarised Fresnel reflectance of a dielectric interface, with
+/// `eta` the relative index of refraction as in `refract`.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Fresnel reflectance of a conductor with complex index of refraction
+/// `eta + i k`, evaluated per colour channel.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: Color, k: Color) -> Color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i * cos_theta_i;
+        let sin2 = 1.0 - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    Color::new(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+/// Complex index of refraction that reproduces a given reflectance at
+/// normal incidence `r` and towards grazing angles `g` (Gulbrandsen 2014),
+/// per colour channel. Returns `(eta, k)`.
+pub fn conductor_from_reflectance(r: Color, g: Color) -> (Color, Color) {
+    let channel = |r: f64, g: f64| {
+        let r = r.clamp(0.0, 0.99);
+        let sqrt_r = r.sqrt();
+        let n_min = (1.0 - r) / (1.0 + r);
+        let n_max = (1.0 + sqrt_r) / (1.0 - sqrt_r);
+        let n = g * n_min + (1.0 - g) * n_max;
+        let k2 = ((n + 1.0) * (n + 1.0) * r - (n - 1.0) * (n - 1.0)) / (1.0 - r);
+        (n, k2.max(0.0).sqrt())
+    };
+
+    let (nx, kx) = channel(r.x, g.x);
+    let (ny, ky) = channel(r.y, g.y);
+    let (nz, kz) = channel(r.z, g.z);
+    (Color::new(nx, ny, nz), Color::new(kx, ky, kz))
+}
+
+#[test]
+fn ggx_normalisation_test() {
+    // The projected microfacet area integrates to one over the hemisphere
+    let ggx = Ggx::from_roughness(0.5, 0.5);
+    let steps = 400;
+    let mut integral = 0.0;
+    for i in 0..steps {
+        let cos_theta = (i as f64 + 0.5) / steps as f64;
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        for j in 0..steps {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / steps as f64;
+            let wm = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            integral += ggx.d(wm) * cos_theta;
+        }
+    }
+    integral *= 2.0 * PI / (steps * steps) as f64;
+
+    assert!((integral - 1.0).abs() < 0.01);
+}
+
+#[test]
+fn fresnel_dielectric_test() {
+    // Glass reflects 4% head on and everything past the critical angle
+    // when seen from the inside
+    assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+    assert_eq!(1.0, fresnel_dielectric(0.5, 1.0 / 1.5));
+
+    let wo = Vec3::new(0.6, 0.0, 0.8);
+    let wi = refract(wo, Vec3::new(0.0, 0.0, 1.0), 1.5).unwrap();
+    assert!((wi.x * 1.5 + wo.x).abs() < 1e-12);
+    assert!((wi.length() - 1.0).abs() < 1e-12);
+}
+
+#[test]
+fn conductor_from_reflectance_test() {
+    let r = Color::new(0.9, 0.6, 0.2);
+    let (eta, k) = conductor_from_reflectance(r, r);
+
+    let f0 = fresnel_conductor(1.0, eta, k);
+
+    assert!((f0 - r).length() < 1e-9);
+}
@@ -0,0 +1,624 @@
+use crate::aov::{Aov, ALL_AOVS};
+use crate::camera::{Exposure, FisheyeMapping, Projection, Stereo, StereoLayout};
+use crate::debug::DebugMode;
+use crate::denoise::DenoiseSettings;
+use crate::film::Filter;
+use crate::lightsampler::LightSampling;
+use crate::pathdebug::TraceFormat;
+use crate::progress::ProgressMode;
+use crate::ray::PathSettings;
+use crate::sky::{SkySettings, SunPosition};
+use crate::stats::StatsFormat;
+use crate::tonemap::{ToneMapOperator, ToneMapper};
+use crate::vector::{Color, Vec3};
+use std::str::FromStr;
+
+/// Settings taken from the command line.
+///
+/// `random` is accepted as a bare word for backwards compatibility, every
+/// other setting is given as `--name value`.
+pub struct RenderOptions {
+    pub random_scene: bool,
+    /// Hangs strings of small lights above the random scene.
+    pub string_lights: bool,
+    pub samples_per_pixel: i32,
+    pub progress: ProgressMode,
+    pub stats: StatsFormat,
+    pub path: PathSettings,
+    pub tone_mapper: ToneMapper,
+    pub filter: Filter,
+    /// Filter radius in pixels, the filter's own default when not given.
+    pub filter_radius: Option<f64>,
+    /// Denoise the image before it is written, `None` when disabled.
+    pub denoise: Option<DenoiseSettings>,
+    /// Extra render passes to write next to the beauty image.
+    pub aovs: Vec<Aov>,
+    /// Prefix of the per pass `.pfm` files.
+    pub aov_prefix: String,
+    /// Multi-layer EXR with the beauty image and every pass.
+    pub exr_path: Option<String>,
+    /// Replaces path tracing with a diagnostic visualisation.
+    pub debug_mode: Option<DebugMode>,
+    /// Makes the render reproducible, every pixel gets its own derived seed.
+    pub seed: Option<u64>,
+    /// Pixel, counted from the top left, whose paths are logged instead of rendering.
+    pub trace_pixel: Option<(usize, usize)>,
+    pub trace_format: TraceFormat,
+    /// Equirectangular `.hdr` image lighting the scene instead of the gradient.
+    pub environment_map: Option<String>,
+    /// Rotation of the environment map about the vertical axis, in degrees.
+    pub environment_rotation: f64,
+    /// Scales the brightness of the environment map, the sky or the gradient.
+    pub environment_intensity: f64,
+    /// Physical sky lighting the scene instead of the gradient.
+    pub sky: Option<SkySettings>,
+    /// How the light that receives the shadow ray of a bounce is picked.
+    pub light_sampling: LightSampling,
+    pub projection: Projection,
+    /// Straight blades closing the aperture, round when not given.
+    pub aperture_blades: Option<usize>,
+    /// Turns the aperture blades, in degrees.
+    pub blade_rotation: f64,
+    /// Grey `.hdr` image giving the shape of the aperture.
+    pub aperture_image: Option<String>,
+    /// Clipping of the aperture by the lens barrel towards the edges of the
+    /// image, 0 for none.
+    pub cats_eye: f64,
+    /// Lens prescription traced instead of the thin lens.
+    pub lens: Option<String>,
+    /// Diagonal of the sensor in millimetres, which sets the aperture size
+    /// for an f-number and the view through a lens.
+    pub sensor_diagonal: f64,
+    /// F-number, shutter time and ISO of the camera, `None` to keep the
+    /// radiance and the fixed aperture of the default camera.
+    pub exposure: Option<Exposure>,
+    /// Lens shift in image widths and heights.
+    pub shift: (f64, f64),
+    /// Tilt of the plane of focus in degrees.
+    pub tilt: f64,
+    /// White point of the extended Reinhard operator, applied once the
+    /// operator is known.
+    white: Option<f64>,
+    /// Eye settings of a stereo panorama, applied to the projection once
+    /// all arguments are read.
+    stereo: Option<Stereo>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            random_scene: false,
+            string_lights: false,
+            samples_per_pixel: 10,
+            progress: ProgressMode::Bar,
+            stats: StatsFormat::Text,
+            path: PathSettings::default(),
+            tone_mapper: ToneMapper::default(),
+            filter: Filter::Box,
+            filter_radius: None,
+            denoise: None,
+            aovs: Vec::new(),
+            aov_prefix: String::from("aov"),
+            exr_path: None,
+            debug_mode: None,
+            seed: None,
+            trace_pixel: None,
+            trace_format: TraceFormat::Text,
+            environment_map: None,
+            environment_rotation: 0.0,
+            environment_intensity: 1.0,
+            sky: None,
+            light_sampling: LightSampling::Bvh,
+            projection: Projection::Perspective,
+            aperture_blades: None,
+            blade_rotation: 0.0,
+            aperture_image: None,
+            cats_eye: 0.0,
+            lens: None,
+            // Full frame 35 mm
+            sensor_diagonal: 43.27,
+            exposure: None,
+            shift: (0.0, 0.0),
+            tilt: 0.0,
+            white: None,
+            stereo: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn from_args(args: &[String]) -> Result<RenderOptions, String> {
+        let mut options = RenderOptions::default();
+        let mut iter = args.iter().skip(1);
+        // A traced lens has its own stop, so an f-number cannot be given
+        let mut f_stop_given = false;
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "random" => options.random_scene = true,
+                "--progress" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.progress = ProgressMode::parse(value)
+                        .ok_or(format!("Unknown progress mode '{}'", value))?;
+                }
+                "--stats" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.stats = StatsFormat::parse(value)
+                        .ok_or(format!("Unknown stats format '{}'", value))?;
+                }
+                "--max-depth" => options.path.max_depth = parse_value(&mut iter, arg)?,
+                "--max-diffuse" => options.path.max_diffuse_depth = parse_value(&mut iter, arg)?,
+                "--max-specular" => options.path.max_specular_depth = parse_value(&mut iter, arg)?,
+                "--max-transmission" => {
+                    options.path.max_transmission_depth = parse_value(&mut iter, arg)?
+                }
+                "--rr-min-bounces" => {
+                    options.path.roulette_min_bounces = parse_value(&mut iter, arg)?
+                }
+                "--tonemap" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.tone_mapper.operator = ToneMapOperator::parse(value)
+                        .ok_or(format!("Unknown tone mapping operator '{}'", value))?;
+                }
+                "--exposure" => options.tone_mapper.exposure = parse_value(&mut iter, arg)?,
+                "--white" => options.white = Some(parse_value(&mut iter, arg)?),
+                "--filter" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.filter = Filter::parse(value)
+                        .ok_or(format!("Unknown reconstruction filter '{}'", value))?;
+                }
+                "--filter-radius" => {
+                    let radius = parse_value(&mut iter, arg)?;
+                    if radius <= 0.0 {
+                        return Err(String::from("The filter radius must be positive"));
+                    }
+                    options.filter_radius = Some(radius);
+                }
+                "--spp" => {
+                    let samples = parse_value(&mut iter, arg)?;
+                    if samples <= 0 {
+                        return Err(String::from("The samples per pixel must be positive"));
+                    }
+                    options.samples_per_pixel = samples;
+                }
+                "--denoise" => {
+                    options.denoise.get_or_insert(DenoiseSettings::default());
+                }
+                "--denoise-radius" => {
+                    options
+                        .denoise
+                        .get_or_insert(DenoiseSettings::default())
+                        .radius = parse_value(&mut iter, arg)?
+                }
+                "--aov" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.aovs = parse_aovs(value)?;
+                }
+                "--aov-prefix" => options.aov_prefix = next_value(&mut iter, arg)?.to_string(),
+                "--exr" => options.exr_path = Some(next_value(&mut iter, arg)?.to_string()),
+                "--debug" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.debug_mode = Some(
+                        DebugMode::parse(value).ok_or(format!("Unknown debug mode '{}'", value))?,
+                    );
+                }
+                "--seed" => options.seed = Some(parse_value(&mut iter, arg)?),
+                "--trace-pixel" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.trace_pixel = Some(parse_pixel(value)?);
+                }
+                "--trace-format" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.trace_format = TraceFormat::parse(value)
+                        .ok_or(format!("Unknown trace format '{}'", value))?;
+                }
+                "--clamp" => options.path.max_sample_radiance = Some(parse_value(&mut iter, arg)?),
+                "--clamp-indirect" => {
+                    options.path.max_indirect_radiance = Some(parse_value(&mut iter, arg)?)
+                }
+                "--regularize" => {
+                    options.path.regularize_roughness = Some(parse_value(&mut iter, arg)?)
+                }
+                "--env-map" => {
+                    options.environment_map = Some(next_value(&mut iter, arg)?.to_string())
+                }
+                "--env-rotation" => options.environment_rotation = parse_value(&mut iter, arg)?,
+                "--env-intensity" => options.environment_intensity = parse_value(&mut iter, arg)?,
+                "--sky" => {
+                    options.sky.get_or_insert(SkySettings::default());
+                }
+                "--sun-direction" => {
+                    let value = next_value(&mut iter, arg)?;
+                    let [x, y, z] = parse_triple(value, arg)?;
+                    options.sky.get_or_insert(SkySettings::default()).sun =
+                        SunPosition::Direction(Vec3::new(x, y, z));
+                }
+                "--sun-time" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.sky.get_or_insert(SkySettings::default()).sun = parse_sun_time(value)?;
+                }
+                "--turbidity" => {
+                    options.sky.get_or_insert(SkySettings::default()).turbidity =
+                        parse_value(&mut iter, arg)?
+                }
+                "--ground-albedo" => {
+                    let value = next_value(&mut iter, arg)?;
+                    let albedo = match value.parse() {
+                        Ok(grey) => [grey; 3],
+                        Err(_) => parse_triple(value, arg)?,
+                    };
+                    options
+                        .sky
+                        .get_or_insert(SkySettings::default())
+                        .ground_albedo = Color::new(albedo[0], albedo[1], albedo[2]);
+                }
+                "--string-lights" => {
+                    options.random_scene = true;
+                    options.string_lights = true;
+                }
+                "--light-sampling" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.light_sampling = LightSampling::parse(value)
+                        .ok_or(format!("Unknown light sampling strategy '{}'", value))?;
+                }
+                "--projection" => {
+                    let value = next_value(&mut iter, arg)?;
+                    let projection = Projection::parse(value)
+                        .ok_or(format!("Unknown projection '{}'", value))?;
+                    // A fisheye angle given earlier is kept
+                    options.projection = match (projection, options.projection) {
+                        (Projection::Fisheye { mapping, .. }, Projection::Fisheye { fov, .. }) => {
+                            Projection::Fisheye { mapping, fov }
+                        }
+                        _ => projection,
+                    };
+                }
+                "--ortho-height" => {
+                    let height = parse_value(&mut iter, arg)?;
+                    if height <= 0.0 {
+                        return Err(String::from("The orthographic height must be positive"));
+                    }
+                    options.projection = Projection::Orthographic { height }
+                }
+                "--fisheye-fov" => {
+                    let fov = parse_value(&mut iter, arg)?;
+                    if fov <= 0.0 || fov > 360.0 {
+                        return Err(String::from(
+                            "The fisheye field of view must be above 0 and at most 360 degrees",
+                        ));
+                    }
+                    let mapping = match options.projection {
+                        Projection::Fisheye { mapping, .. } => mapping,
+                        _ => FisheyeMapping::Equidistant,
+                    };
+                    options.projection = Projection::Fisheye { mapping, fov };
+                }
+                "--stereo" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options.stereo.get_or_insert(Stereo::default()).layout =
+                        StereoLayout::parse(value)
+                            .ok_or(format!("Unknown stereo layout '{}'", value))?;
+                }
+                "--ipd" => {
+                    let ipd = parse_value(&mut iter, arg)?;
+                    if ipd < 0.0 {
+                        return Err(String::from("The eye separation cannot be negative"));
+                    }
+                    options.stereo.get_or_insert(Stereo::default()).ipd = ipd;
+                }
+                "--pole-merge" => {
+                    let angle = parse_value(&mut iter, arg)?;
+                    if !(0.0..=90.0).contains(&angle) {
+                        return Err(String::from(
+                            "The pole merge angle must be between 0 and 90 degrees",
+                        ));
+                    }
+                    options.stereo.get_or_insert(Stereo::default()).pole_merge = angle;
+                }
+                "--aperture-blades" => {
+                    let blades = parse_value(&mut iter, arg)?;
+                    if blades < 3 {
+                        return Err(String::from("An aperture needs at least 3 blades"));
+                    }
+                    options.aperture_blades = Some(blades);
+                }
+                "--blade-rotation" => options.blade_rotation = parse_value(&mut iter, arg)?,
+                "--aperture-image" => {
+                    options.aperture_image = Some(next_value(&mut iter, arg)?.to_string())
+                }
+                "--cats-eye" => options.cats_eye = parse_value(&mut iter, arg)?,
+                "--lens" => options.lens = Some(next_value(&mut iter, arg)?.to_string()),
+                "--sensor" => options.sensor_diagonal = parse_value(&mut iter, arg)?,
+                "--f-stop" => {
+                    f_stop_given = true;
+                    options
+                        .exposure
+                        .get_or_insert_with(Exposure::default)
+                        .f_number = parse_value(&mut iter, arg)?;
+                }
+                "--shutter" => {
+                    let value = next_value(&mut iter, arg)?;
+                    options
+                        .exposure
+                        .get_or_insert_with(Exposure::default)
+                        .shutter = parse_shutter(value)?;
+                }
+                "--iso" => {
+                    options.exposure.get_or_insert_with(Exposure::default).iso =
+                        parse_value(&mut iter, arg)?
+                }
+                "--shift-x" => options.shift.0 = parse_value(&mut iter, arg)?,
+                "--shift-y" => options.shift.1 = parse_value(&mut iter, arg)?,
+                "--tilt" => options.tilt = parse_value(&mut iter, arg)?,
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
+
+        if options.trace_pixel.is_some() && options.seed.is_none() {
+            return Err(String::from(
+                "Tracing a pixel needs --seed so that its paths match a render",
+            ));
+        }
+
+        if options.environment_map.is_some() && options.sky.is_some() {
+            return Err(String::from(
+                "An environment map and the sky cannot be used together",
+            ));
+        }
+
+        if options.aperture_blades.is_some() && options.aperture_image.is_some() {
+            return Err(String::from(
+                "Aperture blades and an aperture image cannot be used together",
+            ));
+        }
+        if let Some(exposure) = options.exposure {
+            if exposure.f_number <= 0.0 || exposure.shutter <= 0.0 || exposure.iso <= 0.0 {
+                return Err(String::from(
+                    "The f-number, shutter time and ISO must be positive",
+                ));
+            }
+        }
+        if options.lens.is_some() && options.tilt != 0.0 {
+            return Err(String::from("A traced lens cannot be tilted"));
+        }
+        if options.lens.is_some() && f_stop_given {
+            return Err(String::from(
+                "The aperture of a traced lens is set by its stop, not --f-stop",
+            ));
+        }
+        if options.lens.is_some() && options.cats_eye != 0.0 {
+            return Err(String::from(
+                "A traced lens vignettes by itself, --cats-eye is for the thin lens",
+            ));
+        }
+        if options.lens.is_some() && options.projection != Projection::Perspective {
+            return Err(String::from(
+                "A lens is only traced with the perspective projection",
+            ));
+        }
+
+        if let Some(white) = options.white {
+            if white <= 0.0 {
+                return Err(String::from("The white point must be positive"));
+            }
+            match &mut options.tone_mapper.operator {
+                ToneMapOperator::ExtendedReinhard { white: point } => *point = white,
+                _ => {
+                    return Err(String::from(
+                        "A white point is only used by the reinhard-extended operator",
+                    ))
+                }
+            }
+        }
+
+        if let Some(stereo) = options.stereo {
+            match options.projection {
+                Projection::Perspective | Projection::Equirectangular { .. } => {
+                    options.projection = Projection::Equirectangular {
+                        stereo: Some(stereo),
+                    }
+                }
+                _ => {
+                    return Err(String::from(
+                        "Stereo is only rendered with the equirectangular projection",
+                    ))
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn next_value<'a>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<&'a str, String> {
+    iter.next()
+        .map(|value| value.as_str())
+        .ok_or(format!("Missing value for '{}'", flag))
+}
+
+/// Parses a comma separated list of passes, or `all`.
+fn parse_aovs(value: &str) -> Result<Vec<Aov>, String> {
+    if value == "all" {
+        return Ok(ALL_AOVS.to_vec());
+    }
+    value
+        .split(',')
+        .map(|name| Aov::parse(name).ok_or(format!("Unknown render pass '{}'", name)))
+        .collect()
+}
+
+/// Parses a pixel position given as `x,y`.
+fn parse_pixel(value: &str) -> Result<(usize, usize), String> {
+    let invalid = || format!("Invalid pixel '{}', expected x,y", value);
+    let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+    Ok((
+        x.parse().map_err(|_| invalid())?,
+        y.parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// Parses a shutter time in seconds, also written as a fraction such as
+/// `1/125`.
+fn parse_shutter(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid shutter time '{}'", value);
+    match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let numerator: f64 = numerator.parse().map_err(|_| invalid())?;
+            let denominator: f64 = denominator.parse().map_err(|_| invalid())?;
+            Ok(numerator / denominator)
+        }
+        None => value.parse().map_err(|_| invalid()),
+    }
+}
+
+/// Parses three comma separated numbers such as `x,y,z`.
+fn parse_triple(value: &str, flag: &str) -> Result<[f64; 3], String> {
+    let invalid = || format!("Invalid value '{}' for '{}', expected x,y,z", value, flag);
+    let numbers: Vec<f64> = value
+        .split(',')
+        .map(|number| number.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    numbers.try_into().map_err(|_| invalid())
+}
+
+/// Parses a local solar time and latitude given as
+/// `YYYY-MM-DD,HH:MM,latitude`.
+fn parse_sun_time(value: &str) -> Result<SunPosition, String> {
+    let invalid = || {
+        format!(
+            "Invalid sun time '{}', expected YYYY-MM-DD,HH:MM,latitude",
+            value
+        )
+    };
+    let parts: Vec<&str> = value.split(',').collect();
+    let [date, time, latitude] = parts[..] else {
+        return Err(invalid());
+    };
+    let date: Vec<u32> = date
+        .split('-')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let [year, month, day] = date[..] else {
+        return Err(invalid());
+    };
+    let (hours, minutes) = time.split_once(':').ok_or_else(invalid)?;
+    let hours: f64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: f64 = minutes.parse().map_err(|_| invalid())?;
+    if !(0.0..24.0).contains(&hours) || !(0.0..60.0).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let mut month_lengths = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if is_leap_year {
+        month_lengths[1] = 29;
+    }
+    if !(1..=12).contains(&month) || day == 0 || day > month_lengths[month as usize - 1] {
+        return Err(invalid());
+    }
+    let day_of_year = month_lengths[..month as usize - 1].iter().sum::<u32>() + day;
+    Ok(SunPosition::Time {
+        day_of_year,
+        hour: hours + minutes / 60.0,
+        latitude: latitude.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn parse_value<'a, T: FromStr>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, String> {
+    let value = next_value(iter, flag)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{}' for '{}'", value, flag))
+}
+
+#[test]
+fn parse_progress_option_test() {
+    let args: Vec<String> = ["ray_tracer_rust", "random", "--progress", "json"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+
+    let options = RenderOptions::from_args(&args).unwrap();
+
+    assert!(options.random_scene);
+    assert_eq!(ProgressMode::Json, options.progress);
+}
+
+#[test]
+fn parse_sun_time_test() {
+    match parse_sun_time("2024-03-01,14:30,52.5").unwrap() {
+        SunPosition::Time {
+            day_of_year,
+            hour,
+            latitude,
+        } => {
+            assert_eq!(61, day_of_year);
+            assert_eq!(14.5, hour);
+            assert_eq!(52.5, latitude);
+        }
+        SunPosition::Direction(_) => panic!("expected a time"),
+    }
+    assert!(parse_sun_time("2024-13-01,14:30,52.5").is_err());
+    assert!(parse_sun_time("2024-02-29,14:30,52.5").is_ok());
+    assert!(parse_sun_time("2023-02-29,14:30,52.5").is_err());
+    assert!(parse_sun_time("2024-04-31,14:30,52.5").is_err());
+    assert!(parse_sun_time("2024-03-01,24:30,52.5").is_err());
+}
+
+#[test]
+fn parse_white_point_test() {
+    let parse = |args: &[&str]| {
+        let args: Vec<String> = ["ray_tracer_rust"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        RenderOptions::from_args(&args)
+    };
+
+    // The white point is kept whichever order the arguments come in
+    for args in [
+        ["--white", "8", "--tonemap", "reinhard-extended"],
+        ["--tonemap", "reinhard-extended", "--white", "8"],
+    ] {
+        let options = parse(&args).unwrap();
+        assert!(matches!(
+            options.tone_mapper.operator,
+            ToneMapOperator::ExtendedReinhard { white } if white == 8.0
+        ));
+    }
+    assert!(parse(&["--white", "8"]).is_err());
+    assert!(parse(&["--white", "8", "--tonemap", "aces"]).is_err());
+    assert!(parse(&["--white", "0", "--tonemap", "reinhard-extended"]).is_err());
+}
+
+#[test]
+fn reject_invalid_options_test() {
+    let parse = |args: &[&str]| {
+        let args: Vec<String> = ["ray_tracer_rust"]
+            .iter()
+            .chain(args)
+            .map(|arg| arg.to_string())
+            .collect();
+        RenderOptions::from_args(&args)
+    };
+
+    let lens = "lenses/double-gauss-50mm.txt";
+    assert!(parse(&["--lens", lens, "--shutter", "1/50"]).is_ok());
+    assert!(parse(&["--lens", lens, "--f-stop", "2"]).is_err());
+    assert!(parse(&["--lens", lens, "--cats-eye", "0.3"]).is_err());
+    assert!(parse(&["--spp", "0"]).is_err());
+    assert!(parse(&["--spp", "-4"]).is_err());
+    assert!(parse(&["--ortho-height", "0"]).is_err());
+    assert!(parse(&["--fisheye-fov", "360"]).is_ok());
+    assert!(parse(&["--fisheye-fov", "400"]).is_err());
+    assert!(parse(&["--ipd", "-0.06"]).is_err());
+    assert!(parse(&["--pole-merge", "-10"]).is_err());
+    assert!(parse(&["--pole-merge", "100"]).is_err());
+}
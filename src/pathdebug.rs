@@ -0,0 +1,437 @@
+use crate::camera::Camera;
+use crate::hittable::HitRecord;
+use crate::material::MATERIAL_NAMES;
+use crate::options::RenderOptions;
+use crate::ray::{render_sample, PathEvent, Ray};
+use crate::util;
+use crate::vector::{Color, Vec3};
+use crate::world::Scene;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    Json,
+}
+
+impl TraceFormat {
+    pub fn parse(name: &str) -> Option<TraceFormat> {
+        match name {
+            "text" => Some(TraceFormat::Text),
+            "json" => Some(TraceFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+struct TracedSample {
+    raster: (f64, f64),
+    ray: Ray,
+    /// Colour added to the film, the path weighted and exposed by the
+    /// camera.
+    color: Color,
+    events: Vec<PathEvent>,
+}
+
+/// Traces every sample of the pixel `(x, y)`, counted from the top left,
+/// and returns a log of each bounce. The pixel is seeded the same way as in
+/// a full render with the same `--seed`, so the paths match that render.
+pub fn trace_pixel(
+    camera: &Camera,
+    scene: &Scene,
+    options: &RenderOptions,
+    image_size: (i32, i32),
+    (x, y): (usize, usize),
+    seed: u64,
+) -> String {
+    let samples = trace_samples(camera, scene, options, image_size, (x, y), seed);
+    match options.trace_format {
+        TraceFormat::Text => format_text(&samples, (x, y), seed),
+        TraceFormat::Json => format_json(&samples, (x, y), seed),
+    }
+}
+
+fn trace_samples(
+    camera: &Camera,
+    scene: &Scene,
+    options: &RenderOptions,
+    (image_width, image_height): (i32, i32),
+    (x, y): (usize, usize),
+    seed: u64,
+) -> Vec<TracedSample> {
+    util::seed(util::pixel_seed(seed, x, y));
+    let row = image_height - 1 - y as i32;
+
+    (0..options.samples_per_pixel)
+        .filter_map(|_| {
+            let mut events = Vec::new();
+            let (raster_x, raster_y, traced) = render_sample(
+                camera,
+                scene,
+                &options.path,
+                (x as i32, row),
+                (image_width, image_height),
+                Some(&mut events),
+            );
+            // Samples outside the image circle of a fisheye have no ray
+            let (ray, weight, sample) = traced?;
+            Some(TracedSample {
+                raster: (raster_x, raster_y),
+                ray,
+                color: camera.exposure_scale() * weight * sample.color,
+                events,
+            })
+        })
+        .collect()
+}
+
+fn format_text(samples: &[TracedSample], (x, y): (usize, usize), seed: u64) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "Pixel ({}, {}), seed {}, {} samples",
+        x,
+        y,
+        seed,
+        samples.len()
+    );
+
+    for (i, traced) in samples.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "Sample {} at ({:.4}, {:.4}): color {}",
+            i,
+            traced.raster.0,
+            traced.raster.1,
+            text_vec(traced.color)
+        );
+        let _ = writeln!(
+            out,
+            "  camera ray origin {} direction {}",
+            text_vec(traced.ray.orig),
+            text_vec(traced.ray.dir)
+        );
+
+        let mut bounce = 0;
+        for event in traced.events.iter() {
+            match event {
+                PathEvent::Hit { hit_record, .. } => {
+                    let _ = writeln!(out, "  bounce {}: {}", bounce, text_hit(hit_record));
+                    bounce += 1;
+                }
+                PathEvent::Scatter {
+                    direction,
+                    attenuation,
+                    pdf,
+                    kind,
+                    throughput,
+                } => {
+                    let pdf = match pdf {
+                        Some(pdf) => format!("{:.6}", pdf),
+                        None => String::from("delta"),
+                    };
+                    let _ = writeln!(
+                        out,
+                        "    scatter {:?} direction {} attenuation {} pdf {} throughput {}",
+                        kind,
+                        text_vec(*direction),
+                        text_vec(*attenuation),
+                        pdf,
+                        text_vec(*throughput)
+                    );
+                }
+                PathEvent::Roulette {
+                    survival_probability,
+                    survived,
+                } => {
+                    let _ = writeln!(
+                        out,
+                        "    russian roulette p {:.4}: {}",
+                        survival_probability,
+                        if *survived { "survived" } else { "terminated" }
+                    );
+                }
+                PathEvent::LightSample {
+                    direction,
+                    radiance,
+                } => {
+                    let _ = writeln!(
+                        out,
+                        "    light sample direction {} radiance {}",
+                        text_vec(*direction),
+                        text_vec(*radiance)
+                    );
+                }
+                PathEvent::Emission { radiance } => {
+                    let _ = writeln!(out, "    emission radiance {}", text_vec(*radiance));
+                }
+                PathEvent::Escaped {
+                    direction,
+                    radiance,
+                } => {
+                    let _ = writeln!(
+                        out,
+                        "  escaped direction {} radiance {}",
+                        text_vec(*direction),
+                        text_vec(*radiance)
+                    );
+                }
+                PathEvent::Absorbed => {
+                    let _ = writeln!(out, "    absorbed");
+                }
+                PathEvent::Terminated { reason } => {
+                    let _ = writeln!(out, "  terminated: {}", reason);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn text_vec(v: Vec3) -> String {
+    format!("({:.4}, {:.4}, {:.4})", v.x, v.y, v.z)
+}
+
+fn text_hit(hit_record: &HitRecord) -> String {
+    format!(
+        "hit t {:.4} point {} normal {} {} face, material {}, object {}",
+        hit_record.t,
+        text_vec(hit_record.p),
+        text_vec(hit_record.normal),
+        if hit_record.is_front_face {
+            "front"
+        } else {
+            "back"
+        },
+        MATERIAL_NAMES[hit_record.material.kind()],
+        hit_record.object_id
+    )
+}
+
+fn format_json(samples: &[TracedSample], (x, y): (usize, usize), seed: u64) -> String {
+    let samples: Vec<String> = samples
+        .iter()
+        .map(|traced| {
+            let events: Vec<String> = traced.events.iter().map(json_event).collect();
+            format!(
+                "{{\"raster\":[{:.6},{:.6}],\"color\":{},\"origin\":{},\"direction\":{},\"events\":[{}]}}",
+                traced.raster.0,
+                traced.raster.1,
+                json_vec(traced.color),
+                json_vec(traced.ray.orig),
+                json_vec(traced.ray.dir),
+                events.join(",")
+            )
+        })
+        .collect();
+
+    format!(
+        "{{\"pixel\":[{},{}],\"seed\":{},\"samples\":[{}]}}\n",
+        x,
+        y,
+        seed,
+        samples.join(",")
+    )
+}
+
+fn json_event(event: &PathEvent) -> String {
+    match event {
+        PathEvent::Hit {
+            hit_record,
+            incoming,
+        } => format!(
+            "{{\"event\":\"hit\",{},\"incoming\":{}}}",
+            json_hit(hit_record),
+            json_vec(*incoming)
+        ),
+        PathEvent::Scatter {
+            direction,
+            attenuation,
+            pdf,
+            kind,
+            throughput,
+        } => {
+            let pdf = match pdf {
+                Some(pdf) => format!("{:.6}", pdf),
+                None => String::from("null"),
+            };
+            format!(
+                "{{\"event\":\"scatter\",\"direction\":{},\"attenuation\":{},\"pdf\":{},\"lobe\":\"{:?}\",\"throughput\":{}}}",
+                json_vec(*direction),
+                json_vec(*attenuation),
+                pdf,
+                kind,
+                json_vec(*throughput)
+            )
+        }
+        PathEvent::Roulette {
+            survival_probability,
+            survived,
+        } => format!(
+            "{{\"event\":\"roulette\",\"survival_probability\":{:.6},\"survived\":{}}}",
+            survival_probability, survived
+        ),
+        PathEvent::LightSample {
+            direction,
+            radiance,
+        } => format!(
+            "{{\"event\":\"light_sample\",\"direction\":{},\"radiance\":{}}}",
+            json_vec(*direction),
+            json_vec(*radiance)
+        ),
+        PathEvent::Emission { radiance } => format!(
+            "{{\"event\":\"emission\",\"radiance\":{}}}",
+            json_vec(*radiance)
+        ),
+        PathEvent::Escaped {
+            direction,
+            radiance,
+        } => format!(
+            "{{\"event\":\"escaped\",\"direction\":{},\"radiance\":{}}}",
+            json_vec(*direction),
+            json_vec(*radiance)
+        ),
+        PathEvent::Absorbed => String::from("{\"event\":\"absorbed\"}"),
+        PathEvent::Terminated { reason } => {
+            format!("{{\"event\":\"terminated\",\"reason\":\"{}\"}}", reason)
+        }
+    }
+}
+
+fn json_vec(v: Vec3) -> String {
+    format!("[{:.6},{:.6},{:.6}]", v.x, v.y, v.z)
+}
+
+fn json_hit(hit_record: &HitRecord) -> String {
+    format!(
+        "\"t\":{:.6},\"point\":{},\"normal\":{},\"front_face\":{},\"material\":\"{}\",\"object\":{}",
+        hit_record.t,
+        json_vec(hit_record.p),
+        json_vec(hit_record.normal),
+        hit_record.is_front_face,
+        MATERIAL_NAMES[hit_record.material.kind()],
+        hit_record.object_id
+    )
+}
+
+#[test]
+fn trace_pixel_matches_render_test() {
+    use crate::environment::Environment;
+    use crate::lightsampler::LightSampling;
+    use crate::vector::Point3;
+    use crate::world;
+
+    util::seed(3);
+    let (objects, lights) = world::random_scene(false);
+    let scene = Scene::new(
+        objects,
+        Environment::Gradient { intensity: 1.0 },
+        lights,
+        LightSampling::Bvh,
+    );
+    let camera = Camera::new(
+        Point3::new(13.0, 2.0, 3.0),
+        Point3::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        40.0,
+        1.5,
+        0.1,
+        10.0,
+    );
+    let mut options = RenderOptions::default();
+    options.samples_per_pixel = 4;
+    options.seed = Some(11);
+    let (size, (x, y)) = ((60, 40), (30, 25));
+
+    // Seeded and sampled the way the render loop does it
+    util::seed(util::pixel_seed(11, x, y));
+    let rendered: Vec<_> = (0..options.samples_per_pixel)
+        .map(|_| {
+            let row = size.1 - 1 - y as i32;
+            let (_, _, traced) =
+                render_sample(&camera, &scene, &options.path, (x as i32, row), size, None);
+            let (_, weight, sample) = traced.unwrap();
+            camera.exposure_scale() * weight * sample.color
+        })
+        .collect();
+
+    // Other pixels traced in between do not change the result
+    trace_samples(&camera, &scene, &options, size, (5, 5), 11);
+    let traced = trace_samples(&camera, &scene, &options, size, (x, y), 11);
+    assert_eq!(rendered.len(), traced.len());
+    for (rendered, traced) in rendered.iter().zip(traced.iter()) {
+        assert_eq!(*rendered, traced.color);
+    }
+}
+
+#[test]
+fn trace_events_follow_their_hit_test() {
+    use crate::environment::Environment;
+    use crate::hittable::{HittableList, Sphere};
+    use crate::lightsampler::LightSampling;
+    use crate::material::MaterialEnum;
+    use crate::vector::Point3;
+
+    // A lamp in front of the camera above a diffuse floor, lit by the sky
+    let lamp = Sphere {
+        center: Point3::new(0.0, 0.0, -3.0),
+        radius: 1.0,
+        material: MaterialEnum::Emissive {
+            radiance: Color::new(4.0, 4.0, 4.0),
+        },
+    };
+    let floor = Sphere {
+        center: Point3::new(0.0, -101.0, -3.0),
+        radius: 100.0,
+        material: MaterialEnum::Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5),
+        },
+    };
+    let objects = HittableList {
+        objects: vec![Box::new(lamp), Box::new(floor)],
+    };
+    let scene = Scene::new(
+        objects,
+        Environment::Gradient { intensity: 1.0 },
+        Vec::new(),
+        LightSampling::Bvh,
+    );
+    let camera = Camera::new(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        60.0,
+        1.0,
+        0.0,
+        3.0,
+    );
+    let mut options = RenderOptions::default();
+    options.samples_per_pixel = 8;
+    let size = (20, 20);
+
+    // The lamp in the middle of the image and the floor at the bottom
+    let mut emissions = 0;
+    for pixel in [(10, 10), (10, 18)] {
+        for traced in trace_samples(&camera, &scene, &options, size, pixel, 5) {
+            let mut at_hit = None;
+            for event in traced.events.iter() {
+                match event {
+                    PathEvent::Hit { hit_record, .. } => {
+                        at_hit = Some(MATERIAL_NAMES[hit_record.material.kind()]);
+                    }
+                    PathEvent::Emission { .. } => {
+                        assert_eq!(Some("emissive"), at_hit);
+                        emissions += 1;
+                    }
+                    PathEvent::LightSample { .. } => assert!(at_hit.is_some()),
+                    PathEvent::Scatter { .. } | PathEvent::Absorbed => {
+                        assert!(at_hit.take().is_some());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    assert!(emissions > 0);
+}
@@ -0,0 +1,310 @@
+use crate::frame::sample_cosine_hemisphere;
+use crate::microfacet::{self, Ggx};
+use crate::util::PI;
+use crate::vector::{luminance, Color, Vec3};
+
+/// Disney style principled BSDF. Every parameter except `base_color` and
+/// `ior` ranges from 0.0 to 1.0.
+///
+/// Directions are in a local frame around the outward facing normal, so
+/// `wo.z < 0.0` means the surface is seen from inside.
+#[derive(Clone, Copy, Debug)]
+pub struct Principled {
+    pub base_color: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Reflectance of the dielectric part at normal incidence, 0.5 is 4%.
+    pub specular: f64,
+    /// Tints the dielectric reflection towards the base colour.
+    pub specular_tint: f64,
+    /// Retro-reflective rim for cloth.
+    pub sheen: f64,
+    /// Second, colourless and glossy specular layer on top.
+    pub clearcoat: f64,
+    pub transmission: f64,
+    pub ior: f64,
+}
+
+/// Disney's default sheen tint.
+static SHEEN_TINT: f64 = 0.5;
+/// Clearcoat roughness for Disney's default gloss of 1.0.
+static CLEARCOAT_ALPHA: f64 = 0.001;
+
+/// One lobe of the BSDF, each with its own sampling routine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Clearcoat,
+    Glass,
+}
+
+static LOBES: [Lobe; 4] = [Lobe::Diffuse, Lobe::Specular, Lobe::Clearcoat, Lobe::Glass];
+
+impl Principled {
+    fn ggx(&self) -> Ggx {
+        Ggx::from_roughness(self.roughness, 0.0)
+    }
+
+    fn tint(&self) -> Color {
+        let luminance = luminance(self.base_color);
+        if luminance > 0.0 {
+            self.base_color / luminance
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        }
+    }
+
+    /// Reflectance of the specular lobe at normal incidence.
+    fn specular_f0(&self) -> Color {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let dielectric = self.specular * 0.08 * lerp(white, self.tint(), self.specular_tint);
+        lerp(dielectric, self.base_color, self.metallic)
+    }
+
+    fn lobe_weight(&self, lobe: Lobe) -> f64 {
+        let dielectric = 1.0 - self.metallic;
+        match lobe {
+            Lobe::Diffuse => dielectric * (1.0 - self.transmission),
+            Lobe::Specular => 1.0 - dielectric * self.transmission,
+            Lobe::Clearcoat => 0.25 * self.clearcoat,
+            Lobe::Glass => dielectric * self.transmission,
+        }
+    }
+
+    /// Probability of sampling each lobe, roughly proportional to how much
+    /// light it reflects.
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        let mut probabilities = LOBES.map(|lobe| {
+            let albedo = match lobe {
+                Lobe::Diffuse => luminance(self.base_color),
+                Lobe::Specular => luminance(self.specular_f0()).max(0.1),
+                Lobe::Clearcoat => 0.04,
+                Lobe::Glass => 1.0,
+            };
+            self.lobe_weight(lobe) * albedo
+        });
+        let total: f64 = probabilities.iter().sum();
+        if total > 0.0 {
+            probabilities.iter_mut().for_each(|p| *p /= total);
+        } else {
+            probabilities = [1.0, 0.0, 0.0, 0.0];
+        }
+        probabilities
+    }
+
+    /// Flips both directions so that `wo` is above the surface and returns
+    /// the relative index of refraction across it.
+    fn orient(&self, wo: Vec3, wi: Vec3) -> (Vec3, Vec3, f64) {
+        if wo.z < 0.0 {
+            let flip = Vec3::new(1.0, 1.0, -1.0);
+            (wo * flip, wi * flip, 1.0 / self.ior)
+        } else {
+            (wo, wi, self.ior)
+        }
+    }
+
+    /// BSDF value for light arriving from `wi` and leaving towards `wo`.
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Color {
+        let (wo, wi, eta) = self.orient(wo, wi);
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return Color::default();
+        }
+        if wi.z < 0.0 {
+            return self.lobe_weight(Lobe::Glass) * self.glass_transmission(wo, wi, eta);
+        }
+
+        let wm = (wo + wi).unit_vector();
+        let cos_d = wi.dot(wm);
+        let ggx = self.ggx();
+
+        // Burley diffuse with a retro-reflective rim, plus sheen
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let diffuse_fresnel = (1.0 + (fd90 - 1.0) * schlick_weight(wo.z))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(wi.z));
+        let white = Color::new(1.0, 1.0, 1.0);
+        let sheen = self.sheen * lerp(white, self.tint(), SHEEN_TINT) * schlick_weight(cos_d);
+        let diffuse = diffuse_fresnel / PI * self.base_color + sheen;
+
+        let f0 = self.specular_f0();
+        let fresnel = f0 + (white - f0) * schlick_weight(cos_d);
+        let specular = ggx.eval_reflection(wo, wi) * fresnel;
+
+        let clearcoat_ggx = clearcoat_masking();
+        let clearcoat = gtr1(wm.z, CLEARCOAT_ALPHA) * clearcoat_ggx.g1(wo) * clearcoat_ggx.g1(wi)
+            / (4.0 * wo.z * wi.z)
+            * (0.04 + 0.96 * schlick_weight(cos_d));
+
+        let glass = ggx.eval_dielectric(eta, wo, wi);
+
+        self.lobe_weight(Lobe::Diffuse) * diffuse
+            + self.lobe_weight(Lobe::Specular) * specular
+            + self.lobe_weight(Lobe::Clearcoat) * clearcoat * white
+            + self.lobe_weight(Lobe::Glass) * glass * white
+    }
+
+    /// Refraction through the rough interface. The tint is the square root
+    /// of the base colour so that entering and leaving a solid applies it
+    /// once.
+    fn glass_transmission(&self, wo: Vec3, wi: Vec3, eta: f64) -> Color {
+        let tint = Color::new(
+            self.base_color.x.sqrt(),
+            self.base_color.y.sqrt(),
+            self.base_color.z.sqrt(),
+        );
+        self.ggx().eval_dielectric(eta, wo, wi) * tint
+    }
+
+    /// Density of `sample` returning `wi`, over solid angle.
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f64 {
+        let (wo, wi, eta) = self.orient(wo, wi);
+        if wo.z == 0.0 || wi.z == 0.0 {
+            return 0.0;
+        }
+        let probabilities = self.lobe_probabilities();
+        LOBES
+            .iter()
+            .zip(probabilities.iter())
+            .filter(|(_, p)| **p > 0.0)
+            .map(|(lobe, p)| p * self.lobe_pdf(*lobe, wo, wi, eta))
+            .sum()
+    }
+
+    fn lobe_pdf(&self, lobe: Lobe, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        let reflected = wi.z > 0.0;
+        match lobe {
+            Lobe::Diffuse if reflected => wi.z / PI,
+            Lobe::Specular => self.ggx().reflection_pdf(wo, wi),
+            Lobe::Clearcoat if reflected => {
+                let wm = (wo + wi).unit_vector();
+                if wo.dot(wm) <= 0.0 {
+                    return 0.0;
+                }
+                gtr1(wm.z, CLEARCOAT_ALPHA) * wm.z / (4.0 * wo.dot(wm))
+            }
+            Lobe::Glass => self.ggx().dielectric_pdf(eta, wo, wi),
+            _ => 0.0,
+        }
+    }
+
+    /// Picks a lobe with `u[0]` and samples a direction from it with the
+    /// remaining numbers. `None` when the sample points into the surface.
+    pub fn sample(&self, wo: Vec3, u: [f64; 3]) -> Option<(Vec3, Lobe)> {
+        let probabilities = self.lobe_probabilities();
+        let mut index = 0;
+        let mut cumulative = probabilities[0];
+        while u[0] >= cumulative && index < LOBES.len() - 1 {
+            index += 1;
+            cumulative += probabilities[index];
+        }
+        let lobe = LOBES[index];
+
+        let flip = if wo.z < 0.0 { -1.0 } else { 1.0 };
+        let oriented_wo = Vec3::new(wo.x, wo.y, wo.z * flip);
+        let eta = if wo.z < 0.0 { 1.0 / self.ior } else { self.ior };
+
+        let wi = match lobe {
+            Lobe::Diffuse => sample_cosine_hemisphere(u[1], u[2]),
+            Lobe::Specular => self.ggx().sample_reflection(oriented_wo, u[1], u[2])?,
+            Lobe::Clearcoat => {
+                let wm = sample_gtr1(CLEARCOAT_ALPHA, u[1], u[2]);
+                microfacet::reflect(oriented_wo, wm)
+            }
+            Lobe::Glass => {
+                // Reuse the lobe choice number to pick reflection over refraction
+                let u_fresnel = (u[0] - (cumulative - probabilities[index])) / probabilities[index];
+                self.ggx()
+                    .sample_dielectric(eta, oriented_wo, [u_fresnel, u[1], u[2]])?
+            }
+        };
+
+        if wi.z == 0.0 || (wi.z < 0.0 && lobe != Lobe::Glass) {
+            return None;
+        }
+        Some((Vec3::new(wi.x, wi.y, wi.z * flip), lobe))
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+/// Fixed roughness used by Disney for the clearcoat masking term.
+fn clearcoat_masking() -> Ggx {
+    Ggx {
+        alpha_x: 0.25,
+        alpha_y: 0.25,
+    }
+}
+
+/// Generalised Trowbridge-Reitz with a long tail, used for the clearcoat.
+fn gtr1(cos_theta: f64, alpha: f64) -> f64 {
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_theta * cos_theta))
+}
+
+fn sample_gtr1(alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    let a2 = alpha * alpha;
+    let cos_theta = ((1.0 - a2.powf(1.0 - u1)) / (1.0 - a2)).max(0.0).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * u2;
+    Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+#[test]
+fn principled_sample_matches_pdf_test() {
+    // The estimate of the reflected energy must not depend on which lobe
+    // happened to be sampled, compare against a brute force integral
+    crate::util::seed(3);
+    let material = Principled {
+        base_color: Color::new(0.8, 0.5, 0.2),
+        metallic: 0.3,
+        roughness: 0.5,
+        specular: 0.5,
+        specular_tint: 0.2,
+        sheen: 0.5,
+        clearcoat: 0.0,
+        transmission: 0.3,
+        ior: 1.5,
+    };
+    let wo = Vec3::new(0.4, 0.1, 0.9).unit_vector();
+
+    let steps = 400;
+    let mut expected = Color::default();
+    for i in 0..steps {
+        let cos_theta = 2.0 * (i as f64 + 0.5) / steps as f64 - 1.0;
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        for j in 0..steps {
+            let phi = 2.0 * PI * (j as f64 + 0.5) / steps as f64;
+            let wi = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            expected = expected + material.eval(wo, wi) * cos_theta.abs();
+        }
+    }
+    expected = expected * (4.0 * PI / (steps * steps) as f64);
+
+    let samples = 200_000;
+    let mut estimate = Color::default();
+    for _ in 0..samples {
+        let u = [
+            crate::util::random_double(),
+            crate::util::random_double(),
+            crate::util::random_double(),
+        ];
+        if let Some((wi, _)) = material.sample(wo, u) {
+            let pdf = material.pdf(wo, wi);
+            if pdf > 0.0 {
+                estimate = estimate + material.eval(wo, wi) * (wi.z.abs() / pdf);
+            }
+        }
+    }
+    estimate = estimate / samples as f64;
+
+    assert!((estimate - expected).length() < 0.02);
+}
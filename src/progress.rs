@@ -0,0 +1,239 @@
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Receives render progress updates. Implementations write to stderr so the
+/// image on stdout stays untouched.
+pub trait ProgressReporter {
+    fn start(&mut self, total_samples: u64);
+    fn update(&mut self, samples_done: u64, rays_traced: u64);
+    fn finish(&mut self, samples_done: u64, rays_traced: u64);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressMode {
+    Bar,
+    Json,
+    Silent,
+}
+
+impl ProgressMode {
+    pub fn parse(name: &str) -> Option<ProgressMode> {
+        match name {
+            "bar" => Some(ProgressMode::Bar),
+            "json" => Some(ProgressMode::Json),
+            "none" => Some(ProgressMode::Silent),
+            _ => None,
+        }
+    }
+
+    pub fn reporter(self) -> Box<dyn ProgressReporter> {
+        match self {
+            ProgressMode::Bar => Box::new(TerminalProgress::new()),
+            ProgressMode::Json => Box::new(JsonProgress::new()),
+            ProgressMode::Silent => Box::new(SilentProgress),
+        }
+    }
+}
+
+/// Snapshot of throughput numbers shared by the reporters.
+struct Rates {
+    fraction: f64,
+    samples_per_sec: f64,
+    rays_per_sec: f64,
+    eta: Option<Duration>,
+}
+
+impl Rates {
+    fn compute(elapsed: Duration, total: u64, samples_done: u64, rays_traced: u64) -> Rates {
+        let secs = elapsed.as_secs_f64().max(1e-9);
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            samples_done as f64 / total as f64
+        };
+        let eta = if samples_done == 0 {
+            None
+        } else {
+            let remaining = total.saturating_sub(samples_done) as f64;
+            Some(Duration::from_secs_f64(
+                secs * remaining / samples_done as f64,
+            ))
+        };
+
+        Rates {
+            fraction,
+            samples_per_sec: samples_done as f64 / secs,
+            rays_per_sec: rays_traced as f64 / secs,
+            eta,
+        }
+    }
+}
+
+static BAR_WIDTH: usize = 30;
+static REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct TerminalProgress {
+    start: Instant,
+    last_draw: Option<Instant>,
+    total: u64,
+}
+
+impl TerminalProgress {
+    fn new() -> Self {
+        TerminalProgress {
+            start: Instant::now(),
+            last_draw: None,
+            total: 0,
+        }
+    }
+
+    fn draw(&self, samples_done: u64, rays_traced: u64) {
+        let rates = Rates::compute(self.start.elapsed(), self.total, samples_done, rays_traced);
+        let filled = ((rates.fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+        let eta = match rates.eta {
+            Some(eta) => format_duration(eta),
+            None => String::from("--:--:--"),
+        };
+
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:5.1}% | {} samples/s | {} rays/s | ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            rates.fraction * 100.0,
+            format_rate(rates.samples_per_sec),
+            format_rate(rates.rays_per_sec),
+            eta
+        );
+        let _ = stderr.flush();
+    }
+}
+
+impl ProgressReporter for TerminalProgress {
+    fn start(&mut self, total_samples: u64) {
+        self.start = Instant::now();
+        self.total = total_samples;
+        self.draw(0, 0);
+    }
+
+    fn update(&mut self, samples_done: u64, rays_traced: u64) {
+        if let Some(last) = self.last_draw {
+            if last.elapsed() < REDRAW_INTERVAL {
+                return;
+            }
+        }
+        self.last_draw = Some(Instant::now());
+        self.draw(samples_done, rays_traced);
+    }
+
+    fn finish(&mut self, samples_done: u64, rays_traced: u64) {
+        self.draw(samples_done, rays_traced);
+        eprintln!();
+        eprintln!("Time elapsed is: {:.2?}", self.start.elapsed());
+    }
+}
+
+/// Emits one JSON object per line so a job scheduler can follow the render.
+pub struct JsonProgress {
+    start: Instant,
+    last_emit: Option<Instant>,
+    total: u64,
+}
+
+impl JsonProgress {
+    fn new() -> Self {
+        JsonProgress {
+            start: Instant::now(),
+            last_emit: None,
+            total: 0,
+        }
+    }
+
+    fn emit(&self, event: &str, samples_done: u64, rays_traced: u64) {
+        let elapsed = self.start.elapsed();
+        let rates = Rates::compute(elapsed, self.total, samples_done, rays_traced);
+        let eta = match rates.eta {
+            Some(eta) => format!("{:.3}", eta.as_secs_f64()),
+            None => String::from("null"),
+        };
+
+        eprintln!(
+            "{{\"event\":\"{}\",\"samples_done\":{},\"samples_total\":{},\"percent\":{:.2},\"samples_per_sec\":{:.1},\"rays_per_sec\":{:.1},\"elapsed_secs\":{:.3},\"eta_secs\":{}}}",
+            event,
+            samples_done,
+            self.total,
+            rates.fraction * 100.0,
+            rates.samples_per_sec,
+            rates.rays_per_sec,
+            elapsed.as_secs_f64(),
+            eta
+        );
+    }
+}
+
+impl ProgressReporter for JsonProgress {
+    fn start(&mut self, total_samples: u64) {
+        self.start = Instant::now();
+        self.total = total_samples;
+        self.emit("start", 0, 0);
+    }
+
+    fn update(&mut self, samples_done: u64, rays_traced: u64) {
+        if let Some(last) = self.last_emit {
+            if last.elapsed() < Duration::from_secs(1) {
+                return;
+            }
+        }
+        self.last_emit = Some(Instant::now());
+        self.emit("progress", samples_done, rays_traced);
+    }
+
+    fn finish(&mut self, samples_done: u64, rays_traced: u64) {
+        self.emit("finish", samples_done, rays_traced);
+    }
+}
+
+pub struct SilentProgress;
+
+impl ProgressReporter for SilentProgress {
+    fn start(&mut self, _total_samples: u64) {}
+
+    fn update(&mut self, _samples_done: u64, _rays_traced: u64) {}
+
+    fn finish(&mut self, _samples_done: u64, _rays_traced: u64) {}
+}
+
+fn format_rate(per_sec: f64) -> String {
+    if per_sec >= 1e6 {
+        format!("{:.2}M", per_sec / 1e6)
+    } else if per_sec >= 1e3 {
+        format!("{:.1}k", per_sec / 1e3)
+    } else {
+        format!("{:.0}", per_sec)
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs / 60) % 60,
+        secs % 60
+    )
+}
+
+#[test]
+fn format_duration_test() {
+    assert_eq!("01:02:03", format_duration(Duration::from_secs(3723)));
+}
+
+#[test]
+fn rates_eta_test() {
+    let rates = Rates::compute(Duration::from_secs(10), 100, 25, 1000);
+
+    assert_eq!(0.25, rates.fraction);
+    assert_eq!(100.0, rates.rays_per_sec);
+    assert_eq!(Some(Duration::from_secs(30)), rates.eta);
+}
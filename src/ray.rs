@@ -1,7 +1,13 @@
-use crate::hittable::{HitRecord, Hittable, HittableList};
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable};
+use crate::light::Light;
+use crate::material::{shading_frame, ScatterKind};
+use crate::stats::{self, Counter};
 use crate::util;
 use crate::vector::{Color, Point3, Vec3};
+use crate::world::Scene;
 
+#[derive(Clone, Copy)]
 pub struct Ray {
     pub orig: Point3,
     pub dir: Vec3,
@@ -17,26 +23,448 @@ impl Ray {
     }
 }
 
-pub fn ray_color(ray: Ray, world: &HittableList, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::new(0.0, 0.0, 0.0);
+/// Limits applied while following a path through the scene.
+#[derive(Clone, Copy)]
+pub struct PathSettings {
+    pub max_depth: i32,
+    pub max_diffuse_depth: i32,
+    pub max_specular_depth: i32,
+    pub max_transmission_depth: i32,
+    /// Bounces before Russian roulette may terminate a path.
+    pub roulette_min_bounces: i32,
+    /// Largest radiance a single camera sample may contribute.
+    pub max_sample_radiance: Option<f64>,
+    /// Largest radiance of a contribution that arrives after two or more bounces.
+    pub max_indirect_radiance: Option<f64>,
+    /// Roughness given to specular and transmissive scattering once the path
+    /// has bounced off a diffuse surface, `None` disables regularisation.
+    pub regularize_roughness: Option<f64>,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            max_depth: 50,
+            max_diffuse_depth: 50,
+            max_specular_depth: 50,
+            max_transmission_depth: 50,
+            roulette_min_bounces: 3,
+            max_sample_radiance: None,
+            max_indirect_radiance: None,
+            regularize_roughness: None,
+        }
+    }
+}
+
+/// Result of tracing one camera path.
+pub struct PathSample {
+    pub color: Color,
+    /// Part of `color` that reached the camera after at most one bounce.
+    pub direct: Color,
+    /// Number of times the path scattered before it ended.
+    pub bounces: i32,
+    /// Surface hit by the camera ray, `None` when it escaped the scene.
+    pub first_hit: Option<HitRecord>,
+}
+
+impl PathSample {
+    /// Albedo at the first hit, or the environment seen by the camera when
+    /// the ray escaped.
+    pub fn albedo(&self) -> Color {
+        match &self.first_hit {
+            Some(hit_record) => hit_record.material.albedo(),
+            None => self.color,
+        }
+    }
+
+    fn add_radiance(&mut self, radiance: Color, bounces: i32, settings: &PathSettings) {
+        if bounces <= 1 {
+            self.direct = self.direct + radiance;
+            self.color = self.color + radiance;
+        } else {
+            self.color = self.color + clamp_radiance(radiance, settings.max_indirect_radiance);
+        }
+    }
+
+    pub fn indirect(&self) -> Color {
+        self.color - self.direct
+    }
+
+    /// World space normal at the first hit, zero when the ray escaped.
+    pub fn normal(&self) -> Vec3 {
+        match &self.first_hit {
+            Some(hit_record) => hit_record.normal,
+            None => Vec3::default(),
+        }
+    }
+}
+
+/// What happened at one step of a path, recorded by `trace_path` for the
+/// single pixel debugger.
+pub enum PathEvent {
+    /// Start of a bounce, the events that follow until the next hit belong
+    /// to this surface.
+    Hit {
+        hit_record: HitRecord,
+        incoming: Vec3,
+    },
+    Scatter {
+        direction: Vec3,
+        attenuation: Color,
+        /// Density of `direction`, `None` for delta distributions.
+        pdf: Option<f64>,
+        kind: ScatterKind,
+        /// Path throughput after this bounce.
+        throughput: Color,
+    },
+    Roulette {
+        survival_probability: f64,
+        survived: bool,
+    },
+    /// Light from the environment or a light source reached by a shadow ray,
+    /// already multiplied by the throughput and any MIS weight.
+    LightSample {
+        direction: Vec3,
+        radiance: Color,
+    },
+    /// Light sent out by an emissive surface the path hit, already
+    /// multiplied by the throughput and the MIS weight.
+    Emission {
+        radiance: Color,
+    },
+    Escaped {
+        direction: Vec3,
+        radiance: Color,
+    },
+    Absorbed,
+    Terminated {
+        reason: &'static str,
+    },
+}
+
+pub fn ray_color(ray: Ray, scene: &Scene, settings: &PathSettings) -> PathSample {
+    trace_path(ray, scene, settings, None)
+}
+
+/// One camera sample of the pixel `(col, row)`, with rows counted from the
+/// bottom. Returns its position on the film as `Camera::sample_pixel` does
+/// and, when the camera has a ray there, the ray, its weight and its path. The render
+/// loop and the pixel tracer both go through here, so seeded samples match.
+pub fn render_sample(
+    camera: &Camera,
+    scene: &Scene,
+    settings: &PathSettings,
+    (col, row): (i32, i32),
+    (image_width, image_height): (i32, i32),
+    log: Option<&mut Vec<PathEvent>>,
+) -> (f64, f64, Option<(Ray, f64, PathSample)>) {
+    let (x, y, ray) = camera.sample_pixel(col, row, image_width, image_height);
+    (
+        x,
+        y,
+        ray.map(|(ray, weight)| (ray, weight, trace_path(ray, scene, settings, log))),
+    )
+}
+
+/// Follows one camera path, optionally recording every step into `log`.
+pub fn trace_path(
+    ray: Ray,
+    scene: &Scene,
+    settings: &PathSettings,
+    log: Option<&mut Vec<PathEvent>>,
+) -> PathSample {
+    let mut sample = follow_path(ray, scene, settings, log);
+    if let Some(max) = settings.max_sample_radiance {
+        let scale = clamp_scale(sample.color, max);
+        sample.color = sample.color * scale;
+        sample.direct = sample.direct * scale;
     }
+    sample
+}
 
-    let mut hit_record: HitRecord = HitRecord::default();
-    if world.hit(&ray, 0.001, util::INFINITY, &mut hit_record) {
-        let mut scattered = Ray::new(Point3::default(), Vec3::default());
-        let mut attenuation: Color = Color::default();
-        if hit_record
-            .material
-            .scatter(&ray, &hit_record, &mut attenuation, &mut scattered)
+/// Factor that brings the brightest channel of `radiance` down to `max`,
+/// scaling all channels alike so the hue is kept.
+fn clamp_scale(radiance: Color, max: f64) -> f64 {
+    let brightest = radiance.max_component();
+    if brightest > max {
+        max / brightest
+    } else {
+        1.0
+    }
+}
+
+fn clamp_radiance(radiance: Color, max: Option<f64>) -> Color {
+    match max {
+        Some(max) => radiance * clamp_scale(radiance, max),
+        None => radiance,
+    }
+}
+
+fn follow_path(
+    ray: Ray,
+    scene: &Scene,
+    settings: &PathSettings,
+    mut log: Option<&mut Vec<PathEvent>>,
+) -> PathSample {
+    let mut ray = ray;
+    let mut sample = PathSample {
+        color: Color::new(0.0, 0.0, 0.0),
+        direct: Color::new(0.0, 0.0, 0.0),
+        bounces: 0,
+        first_hit: None,
+    };
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut bounces = 0;
+    let mut diffuse_bounces = 0;
+    let mut specular_bounces = 0;
+    let mut transmission_bounces = 0;
+    // Density the current ray was sampled with, `None` for camera rays and
+    // delta lobes, which light sampling cannot produce
+    let mut scatter_pdf: Option<f64> = None;
+    // Normal at the previous hit, which the light picked there depended on
+    let mut scatter_normal = Vec3::default();
+
+    loop {
+        if bounces >= settings.max_depth {
+            stats::increment(Counter::MaxDepthTerminations);
+            record(&mut log, || PathEvent::Terminated {
+                reason: "max depth",
+            });
+            return sample;
+        }
+
+        stats::increment(Counter::RaysTraced);
+        let mut hit_record: HitRecord = HitRecord::default();
+        if !scene
+            .world
+            .hit(&ray, 0.001, util::INFINITY, &mut hit_record)
         {
-            return attenuation * ray_color(scattered, world, depth - 1);
+            let weight = match scatter_pdf {
+                Some(pdf) => power_heuristic(pdf, scene.environment.pdf(ray.dir)),
+                None => 1.0,
+            };
+            let radiance = weight * throughput * scene.environment.radiance(ray.dir);
+            sample.add_radiance(radiance, bounces, settings);
+            record(&mut log, || PathEvent::Escaped {
+                direction: ray.dir,
+                radiance,
+            });
+            return sample;
         }
-        return Color::new(0.0, 0.0, 0.0);
+        if bounces == 0 {
+            sample.first_hit = Some(hit_record.clone());
+        }
+        record(&mut log, || PathEvent::Hit {
+            hit_record: hit_record.clone(),
+            incoming: ray.dir,
+        });
+        throughput = throughput * hit_record.material.transmittance(&ray, &hit_record);
+
+        let emitted = hit_record.material.emitted(&hit_record);
+        if emitted != Color::default() {
+            let weight = match scatter_pdf {
+                Some(pdf) => {
+                    let light_pdf = scene.light_pdf(hit_record.object_id, ray.orig, scatter_normal);
+                    power_heuristic(pdf, light_pdf)
+                }
+                None => 1.0,
+            };
+            let radiance = weight * throughput * emitted;
+            sample.add_radiance(radiance, bounces, settings);
+            record(&mut log, || PathEvent::Emission { radiance });
+        }
+
+        if let Some(roughness) = settings.regularize_roughness {
+            if diffuse_bounces > 0 {
+                hit_record.material = hit_record.material.regularized(roughness);
+            }
+        }
+
+        let environment_sample = if scene.environment.is_sampled() {
+            sample_environment(scene, &ray, &hit_record)
+        } else {
+            None
+        };
+        let light_sample = scene
+            .light_sampler
+            .sample(hit_record.p, hit_record.normal, util::random_double())
+            .and_then(|(light, pmf)| {
+                sample_light(scene, &scene.lights[light], pmf, &ray, &hit_record)
+            });
+        for (direction, light) in environment_sample.into_iter().chain(light_sample) {
+            let radiance = throughput * light;
+            sample.add_radiance(radiance, bounces + 1, settings);
+            record(&mut log, || PathEvent::LightSample {
+                direction,
+                radiance,
+            });
+        }
+
+        let (scattered, bsdf_sample) = match hit_record.material.scatter(&ray, &hit_record) {
+            Some(scatter) => scatter,
+            None => {
+                record(&mut log, || PathEvent::Absorbed);
+                return sample;
+            }
+        };
+        stats::record_scatter(hit_record.material.kind());
+
+        let kind = bsdf_sample.flags.kind;
+
+        let (lobe_bounces, lobe_limit) = match kind {
+            ScatterKind::Diffuse => (&mut diffuse_bounces, settings.max_diffuse_depth),
+            ScatterKind::Specular => (&mut specular_bounces, settings.max_specular_depth),
+            ScatterKind::Transmission => {
+                (&mut transmission_bounces, settings.max_transmission_depth)
+            }
+        };
+        *lobe_bounces += 1;
+        if *lobe_bounces > lobe_limit {
+            stats::increment(Counter::MaxDepthTerminations);
+            record(&mut log, || PathEvent::Terminated {
+                reason: "lobe depth",
+            });
+            return sample;
+        }
+
+        scatter_normal = hit_record.normal;
+        let attenuation = bsdf_sample.weight();
+        throughput = throughput * attenuation;
+        record(&mut log, || PathEvent::Scatter {
+            pdf: if bsdf_sample.flags.is_delta {
+                None
+            } else {
+                Some(bsdf_sample.pdf)
+            },
+            direction: scattered.dir,
+            attenuation,
+            kind,
+            throughput,
+        });
+        ray = scattered;
+        scatter_pdf = if bsdf_sample.flags.is_delta {
+            None
+        } else {
+            Some(bsdf_sample.pdf)
+        };
+        bounces += 1;
+        sample.bounces = bounces;
+
+        // Russian roulette: dim paths are terminated with probability 1 - p and
+        // the survivors are boosted by 1 / p, which keeps the estimate unbiased.
+        if bounces >= settings.roulette_min_bounces {
+            let p = throughput.max_component().min(0.95);
+            let survived = p > 0.0 && util::random_double() < p;
+            record(&mut log, || PathEvent::Roulette {
+                survival_probability: p,
+                survived,
+            });
+            if !survived {
+                stats::increment(Counter::RouletteTerminations);
+                return sample;
+            }
+            throughput = throughput / p;
+        }
+    }
+}
+
+/// Events are only built when a log was requested.
+fn record(log: &mut Option<&mut Vec<PathEvent>>, event: impl FnOnce() -> PathEvent) {
+    if let Some(log) = log.as_deref_mut() {
+        log.push(event());
+    }
+}
+
+/// Next event estimation: light from a direction sampled on the environment
+/// that is not blocked by the scene, weighted against finding the same
+/// direction by BSDF sampling. Returns the direction and the radiance it
+/// contributes, `None` when nothing arrives.
+fn sample_environment(scene: &Scene, ray: &Ray, hit_record: &HitRecord) -> Option<(Vec3, Color)> {
+    let u = [util::random_double(), util::random_double()];
+    let (direction, radiance, light_pdf) = scene.environment.sample(u)?;
+
+    let frame = shading_frame(hit_record);
+    let wo = frame.to_local(-ray.dir.unit_vector());
+    let wi = frame.to_local(direction);
+    let material = &hit_record.material;
+    let f = material.eval(wo, wi);
+    if f == Color::default() {
+        return None;
     }
 
-    let unit_direction = ray.dir.unit_vector();
-    let t = 0.5 * (unit_direction.y + 1.0);
-    // Interpolate from white to blue
-    (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    if is_occluded(scene, hit_record.p, direction, util::INFINITY) {
+        return None;
+    }
+
+    let weight = power_heuristic(light_pdf, material.pdf(wo, wi));
+    Some((direction, f * radiance * (wi.z.abs() * weight / light_pdf)))
+}
+
+/// Light from a light source, picked with probability `pmf`, that is not
+/// blocked by the scene. Area lights are weighted against finding the same
+/// direction by BSDF sampling.
+fn sample_light(
+    scene: &Scene,
+    light: &Light,
+    pmf: f64,
+    ray: &Ray,
+    hit_record: &HitRecord,
+) -> Option<(Vec3, Color)> {
+    let u = [util::random_double(), util::random_double()];
+    let light_sample = light.sample(hit_record.p, u)?;
+
+    let frame = shading_frame(hit_record);
+    let wo = frame.to_local(-ray.dir.unit_vector());
+    let wi = frame.to_local(light_sample.direction);
+    let material = &hit_record.material;
+    let f = material.eval(wo, wi);
+    if f == Color::default() {
+        return None;
+    }
+
+    let (direction, distance) = (light_sample.direction, light_sample.distance);
+    if is_occluded(scene, hit_record.p, direction, distance) {
+        return None;
+    }
+    let contribution = f * light_sample.radiance * (wi.z.abs() / pmf);
+    match light_sample.pdf {
+        Some(pdf) => {
+            let pdf = pmf * pdf;
+            let weight = power_heuristic(pdf, material.pdf(wo, wi));
+            Some((direction, contribution * (weight / pdf)))
+        }
+        None => Some((direction, contribution)),
+    }
+}
+
+/// Traces a shadow ray, which is blocked by anything closer than `distance`.
+fn is_occluded(scene: &Scene, origin: Point3, direction: Vec3, distance: f64) -> bool {
+    stats::increment(Counter::RaysTraced);
+    let shadow_ray = Ray::new(origin, direction);
+    let mut blocker = HitRecord::default();
+    scene
+        .world
+        .hit(&shadow_ray, 0.001, distance - 0.001, &mut blocker)
+}
+
+/// Multiple importance sampling weight of a strategy with density `pdf`
+/// against one with density `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    a / (a + b)
+}
+
+#[test]
+fn clamp_radiance_keeps_hue_test() {
+    let radiance = Color::new(8.0, 4.0, 2.0);
+
+    assert_eq!(
+        Color::new(2.0, 1.0, 0.5),
+        clamp_radiance(radiance, Some(2.0))
+    );
+    assert_eq!(radiance, clamp_radiance(radiance, Some(10.0)));
+    assert_eq!(radiance, clamp_radiance(radiance, None));
 }
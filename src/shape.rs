@@ -0,0 +1,393 @@
+use crate::bounds::Bounds;
+use crate::frame::{self, Frame};
+use crate::util::PI;
+use crate::vector::{Point3, Vec3};
+
+/// Geometry of an emissive object, sampled by the solid angle it covers as
+/// seen from the shading point rather than by its area.
+#[derive(Clone, Copy)]
+pub enum Shape {
+    Sphere {
+        center: Point3,
+        radius: f64,
+    },
+    /// Parallelogram spanned by the edges `u` and `v` from `corner`, facing
+    /// along `u × v`.
+    Quad {
+        corner: Point3,
+        u: Vec3,
+        v: Vec3,
+    },
+    /// Triangle facing along `(b - a) × (c - a)`.
+    Triangle {
+        a: Point3,
+        b: Point3,
+        c: Point3,
+    },
+}
+
+/// Point on a shape picked from a shading point.
+pub struct ShapeSample {
+    /// Unit direction from the shading point towards the shape.
+    pub direction: Vec3,
+    pub distance: f64,
+    /// Normal on the front side of the shape at the sampled point.
+    pub normal: Vec3,
+    /// Density of `direction` over solid angle.
+    pub pdf: f64,
+}
+
+impl Shape {
+    pub fn sample(&self, p: Point3, u: [f64; 2]) -> Option<ShapeSample> {
+        let (direction, pdf) = match *self {
+            Shape::Sphere { center, radius } => {
+                // Cone of directions towards the visible cap
+                let to_center = center - p;
+                let sin_theta_max2 = radius * radius / to_center.length_squared();
+                if sin_theta_max2 >= 1.0 {
+                    return None;
+                }
+                let cos_theta_max = (1.0 - sin_theta_max2).sqrt();
+                let local = frame::sample_cone(u[0], u[1], cos_theta_max);
+                let direction = Frame::from_normal(to_center.unit_vector()).to_world(local);
+                (direction, frame::cone_pdf(cos_theta_max))
+            }
+            Shape::Quad {
+                corner,
+                u: eu,
+                v: ev,
+            } => {
+                if eu.dot(ev).abs() < 1e-9 * eu.length() * ev.length() {
+                    sample_spherical_rectangle(p, corner, eu, ev, u)?
+                } else {
+                    // A slanted parallelogram is sampled as two triangles
+                    let halves = [
+                        [corner, corner + eu, corner + eu + ev],
+                        [corner, corner + eu + ev, corner + ev],
+                    ];
+                    let first = triangle_solid_angle(p, halves[0]);
+                    let total = first + triangle_solid_angle(p, halves[1]);
+                    let probability = first / total;
+                    let (direction, _) = if u[0] < probability {
+                        sample_spherical_triangle(p, halves[0], [u[0] / probability, u[1]])?
+                    } else {
+                        let u0 = (u[0] - probability) / (1.0 - probability);
+                        sample_spherical_triangle(p, halves[1], [u0, u[1]])?
+                    };
+                    (direction, 1.0 / total)
+                }
+            }
+            Shape::Triangle { a, b, c } => sample_spherical_triangle(p, [a, b, c], u)?,
+        };
+
+        let (distance, normal) = self.intersect(p, direction)?;
+        Some(ShapeSample {
+            direction,
+            distance,
+            normal,
+            pdf,
+        })
+    }
+
+    /// Density of `sample` over solid angle for a direction from `p` that
+    /// is known to reach the shape.
+    pub fn pdf(&self, p: Point3) -> f64 {
+        let solid_angle = match *self {
+            Shape::Sphere { center, radius } => {
+                let sin_theta_max2 = radius * radius / (center - p).length_squared();
+                if sin_theta_max2 >= 1.0 {
+                    return 0.0;
+                }
+                return frame::cone_pdf((1.0 - sin_theta_max2).sqrt());
+            }
+            Shape::Quad { corner, u, v } => {
+                triangle_solid_angle(p, [corner, corner + u, corner + u + v])
+                    + triangle_solid_angle(p, [corner, corner + u + v, corner + v])
+            }
+            Shape::Triangle { a, b, c } => triangle_solid_angle(p, [a, b, c]),
+        };
+        if solid_angle > 0.0 {
+            1.0 / solid_angle
+        } else {
+            0.0
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        match *self {
+            Shape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            Shape::Quad { u, v, .. } => u.cross(v).length(),
+            Shape::Triangle { a, b, c } => 0.5 * (b - a).cross(c - a).length(),
+        }
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let extent = Vec3::new(radius, radius, radius);
+                Bounds {
+                    min: center - extent,
+                    max: center + extent,
+                }
+            }
+            Shape::Quad { corner, u, v } => {
+                Bounds::from_points(&[corner, corner + u, corner + v, corner + u + v])
+            }
+            Shape::Triangle { a, b, c } => Bounds::from_points(&[a, b, c]),
+        }
+    }
+
+    /// Distance along the unit `direction` from `p` to the shape and the
+    /// front facing normal there.
+    fn intersect(&self, p: Point3, direction: Vec3) -> Option<(f64, Vec3)> {
+        match *self {
+            Shape::Sphere { center, radius } => {
+                let oc = p - center;
+                let half_b = oc.dot(direction);
+                let c = oc.length_squared() - radius * radius;
+                // Directions grazing the cap can miss by rounding, they
+                // touch it where the discriminant is zero
+                let t = -half_b - (half_b * half_b - c).max(0.0).sqrt();
+                if t <= 0.0 {
+                    return None;
+                }
+                let normal = (p + t * direction - center) / radius;
+                Some((t, normal))
+            }
+            Shape::Quad { corner, u, v } => plane_distance(p, direction, corner, u.cross(v)),
+            Shape::Triangle { a, b, c } => plane_distance(p, direction, a, (b - a).cross(c - a)),
+        }
+    }
+}
+
+fn plane_distance(p: Point3, direction: Vec3, point: Point3, normal: Vec3) -> Option<(f64, Vec3)> {
+    let denominator = normal.dot(direction);
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = normal.dot(point - p) / denominator;
+    if t <= 0.0 {
+        return None;
+    }
+    Some((t, normal.unit_vector()))
+}
+
+/// Solid angle of a triangle seen from `p` (Van Oosterom and Strackee).
+fn triangle_solid_angle(p: Point3, vertices: [Point3; 3]) -> f64 {
+    let [a, b, c] = vertices.map(|vertex| (vertex - p).unit_vector());
+    let numerator = a.dot(b.cross(c)).abs();
+    let denominator = 1.0 + a.dot(b) + b.dot(c) + c.dot(a);
+    2.0 * numerator.atan2(denominator)
+}
+
+/// Angle between two unit vectors, accurate for nearly parallel ones.
+fn angle_between(v1: Vec3, v2: Vec3) -> f64 {
+    if v1.dot(v2) < 0.0 {
+        PI - 2.0 * ((v1 + v2).length() / 2.0).min(1.0).asin()
+    } else {
+        2.0 * ((v2 - v1).length() / 2.0).min(1.0).asin()
+    }
+}
+
+/// Part of `v` orthogonal to the unit vector `w`.
+fn gram_schmidt(v: Vec3, w: Vec3) -> Vec3 {
+    v - v.dot(w) * w
+}
+
+/// Uniform direction towards a parallelogram, assuming its edges are
+/// orthogonal (Ureña et al. 2013). Returns the direction and its density.
+fn sample_spherical_rectangle(
+    p: Point3,
+    corner: Point3,
+    eu: Vec3,
+    ev: Vec3,
+    u: [f64; 2],
+) -> Option<(Vec3, f64)> {
+    let (width, height) = (eu.length(), ev.length());
+    let mut frame = Frame {
+        s: eu / width,
+        t: ev / height,
+        n: (eu / width).cross(ev / height),
+    };
+    let local = frame.to_local(corner - p);
+    let mut z0 = local.z;
+    // Work on the side of the rectangle facing away from `p`
+    if z0 > 0.0 {
+        frame.n = -frame.n;
+        z0 = -z0;
+    }
+    if z0 == 0.0 {
+        return None;
+    }
+    let (x0, y0) = (local.x, local.y);
+    let (x1, y1) = (x0 + width, y0 + height);
+
+    let v00 = Vec3::new(x0, y0, z0);
+    let v01 = Vec3::new(x0, y1, z0);
+    let v10 = Vec3::new(x1, y0, z0);
+    let v11 = Vec3::new(x1, y1, z0);
+    let n0 = v00.cross(v10).unit_vector();
+    let n1 = v10.cross(v11).unit_vector();
+    let n2 = v11.cross(v01).unit_vector();
+    let n3 = v01.cross(v00).unit_vector();
+
+    let g0 = angle_between(-n0, n1);
+    let g1 = angle_between(-n1, n2);
+    let g2 = angle_between(-n2, n3);
+    let g3 = angle_between(-n3, n0);
+    let (b0, b1) = (n0.z, n2.z);
+    let k = 2.0 * PI - g2 - g3;
+    let solid_angle = g0 + g1 - k;
+    if solid_angle <= 0.0 {
+        return None;
+    }
+
+    // Pick a vertical strip by its solid angle, then a height within it
+    let au = u[0] * (g0 + g1 - 2.0 * PI) + (u[0] - 1.0) * (g2 + g3);
+    let fu = (au.cos() * b0 - b1) / au.sin();
+    let cu = (1.0 / (fu * fu + b0 * b0).sqrt())
+        .copysign(fu)
+        .clamp(-1.0, 1.0);
+    let xu = (-(cu * z0) / (1.0 - cu * cu).max(0.0).sqrt()).clamp(x0, x1);
+
+    let d = (xu * xu + z0 * z0).sqrt();
+    let h0 = y0 / (d * d + y0 * y0).sqrt();
+    let h1 = y1 / (d * d + y1 * y1).sqrt();
+    let hv = h0 + u[1] * (h1 - h0);
+    let yv = if hv * hv < 1.0 - 1e-6 {
+        hv * d / (1.0 - hv * hv).sqrt()
+    } else {
+        y1
+    };
+
+    let direction = frame.to_world(Vec3::new(xu, yv, z0)).unit_vector();
+    Some((direction, 1.0 / solid_angle))
+}
+
+/// Uniform direction towards a triangle (Arvo 1995). Returns the direction
+/// and its density.
+fn sample_spherical_triangle(p: Point3, vertices: [Point3; 3], u: [f64; 2]) -> Option<(Vec3, f64)> {
+    let [a, b, c] = vertices.map(|vertex| (vertex - p).unit_vector());
+    let n_ab = a.cross(b);
+    let n_bc = b.cross(c);
+    let n_ca = c.cross(a);
+    if [n_ab, n_bc, n_ca].iter().any(|n| n.length_squared() == 0.0) {
+        return None;
+    }
+    let (n_ab, n_bc, n_ca) = (n_ab.unit_vector(), n_bc.unit_vector(), n_ca.unit_vector());
+
+    // Interior angles of the spherical triangle, whose excess is its area
+    let alpha = angle_between(n_ab, -n_ca);
+    let beta = angle_between(n_bc, -n_ab);
+    let gamma = angle_between(n_ca, -n_bc);
+    let solid_angle = alpha + beta + gamma - PI;
+    if solid_angle <= 0.0 {
+        return None;
+    }
+
+    // Find the vertex c' so that triangle a b c' has the sampled area
+    let area_pi = PI + u[0] * solid_angle;
+    let (sin_alpha, cos_alpha) = alpha.sin_cos();
+    let sin_phi = area_pi.sin() * cos_alpha - area_pi.cos() * sin_alpha;
+    let cos_phi = area_pi.cos() * cos_alpha + area_pi.sin() * sin_alpha;
+    let k1 = cos_phi + cos_alpha;
+    let k2 = sin_phi - sin_alpha * a.dot(b);
+    let cos_bp = ((k2 + (k2 * cos_phi - k1 * sin_phi) * cos_alpha)
+        / ((k2 * sin_phi + k1 * cos_phi) * sin_alpha))
+        .clamp(-1.0, 1.0);
+    let sin_bp = (1.0 - cos_bp * cos_bp).max(0.0).sqrt();
+    let cp = cos_bp * a + sin_bp * gram_schmidt(c, a).unit_vector();
+
+    // Then a point on the arc from b to c'
+    let cos_theta = 1.0 - u[1] * (1.0 - cp.dot(b));
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let direction = cos_theta * b + sin_theta * gram_schmidt(cp, b).unit_vector();
+    Some((direction.unit_vector(), 1.0 / solid_angle))
+}
+
+/// Whether `point` lies on the surface of `shape`.
+#[cfg(test)]
+fn on_shape(shape: &Shape, point: Point3) -> bool {
+    match *shape {
+        Shape::Sphere { center, radius } => ((point - center).length() - radius).abs() < 1e-6,
+        Shape::Quad { corner, u, v } => {
+            let d = point - corner;
+            let n = u.cross(v);
+            let s = n.dot(d.cross(v)) / n.length_squared();
+            let t = n.dot(u.cross(d)) / n.length_squared();
+            (-1e-6..=1.0 + 1e-6).contains(&s) && (-1e-6..=1.0 + 1e-6).contains(&t)
+        }
+        Shape::Triangle { a, b, c } => {
+            let area = (b - a).cross(c - a).length();
+            let sub_areas = (b - point).cross(c - point).length()
+                + (c - point).cross(a - point).length()
+                + (a - point).cross(b - point).length();
+            (sub_areas - area).abs() < 1e-6 * area
+        }
+    }
+}
+
+#[test]
+fn shape_sampling_test() {
+    use crate::util;
+
+    let p = Point3::new(0.3, -0.2, 0.1);
+    let shapes = [
+        Shape::Sphere {
+            center: Point3::new(1.0, 2.0, -3.0),
+            radius: 0.8,
+        },
+        Shape::Quad {
+            corner: Point3::new(-1.0, 1.0, -2.0),
+            u: Vec3::new(2.0, 0.0, 0.0),
+            v: Vec3::new(0.0, 0.5, -1.0),
+        },
+        Shape::Quad {
+            corner: Point3::new(-1.0, 1.0, -2.0),
+            u: Vec3::new(2.0, 0.0, 0.0),
+            v: Vec3::new(0.7, 0.5, -1.0),
+        },
+        Shape::Triangle {
+            a: Point3::new(-1.0, 1.0, -1.0),
+            b: Point3::new(2.0, 0.5, -1.5),
+            c: Point3::new(0.0, 3.0, -0.5),
+        },
+    ];
+
+    util::seed(5);
+    for shape in shapes.iter() {
+        // Every sample lands on the shape with the density of `pdf`
+        let samples = 20_000;
+        let mut mean_direction = Vec3::default();
+        for _ in 0..samples {
+            let u = [util::random_double(), util::random_double()];
+            let sample = shape.sample(p, u).unwrap();
+            assert!((sample.pdf - shape.pdf(p)).abs() < 1e-6 * sample.pdf);
+            assert!(on_shape(shape, p + sample.distance * sample.direction));
+            mean_direction = mean_direction + sample.direction / samples as f64;
+        }
+
+        // The solid angle and, as the samples are uniform, the mean
+        // direction match a brute force search over the sphere
+        let steps = 1500;
+        let d_omega = 4.0 * PI / (2 * steps * steps) as f64;
+        let mut solid_angle = 0.0;
+        let mut direction_sum = Vec3::default();
+        for i in 0..steps {
+            let cos_theta = 1.0 - 2.0 * (i as f64 + 0.5) / steps as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..2 * steps {
+                let phi = PI * (j as f64 + 0.5) / steps as f64;
+                let w = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                if let Some((t, _)) = shape.intersect(p, w) {
+                    if on_shape(shape, p + t * w) {
+                        solid_angle += d_omega;
+                        direction_sum = direction_sum + w * d_omega;
+                    }
+                }
+            }
+        }
+        assert!((solid_angle * shape.pdf(p) - 1.0).abs() < 0.01);
+        assert!((mean_direction - direction_sum / solid_angle).length() < 0.01);
+    }
+}
@@ -0,0 +1,386 @@
+use crate::environment::{self, EnvironmentMap};
+use crate::frame::{self, Frame};
+use crate::hdr::HdrImage;
+use crate::util::{self, PI};
+use crate::vector::{luminance, Color, Vec3};
+
+/// Radiance of 1 corresponds to 30 000 cd/m², which makes a white surface
+/// under the midday sun about as bright as under the default gradient.
+pub static LUMINANCE_SCALE: f64 = 1.0 / 30_000.0;
+/// Illuminance of the sun above the atmosphere, in lux.
+static SOLAR_ILLUMINANCE: f64 = 128_000.0;
+static SUN_ANGULAR_RADIUS: f64 = 0.004_65;
+/// Wavelengths in micrometres the sun's transmittance is evaluated at for
+/// the red, green and blue channels.
+static CHANNEL_WAVELENGTHS: [f64; 3] = [0.65, 0.55, 0.45];
+/// Resolution of the table the sky is importance sampled with.
+static TABLE_WIDTH: usize = 128;
+static TABLE_HEIGHT: usize = 64;
+
+/// Parameters of the physical sky, taken from the command line.
+#[derive(Clone, Copy)]
+pub struct SkySettings {
+    pub sun: SunPosition,
+    /// Haziness of the atmosphere, 2 is a very clear day and 10 is hazy.
+    pub turbidity: f64,
+    /// Reflectance of the ground seen below the horizon.
+    pub ground_albedo: Color,
+}
+
+impl Default for SkySettings {
+    fn default() -> Self {
+        SkySettings {
+            sun: SunPosition::Direction(Vec3::new(0.4, 0.6, 0.7)),
+            turbidity: 3.0,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum SunPosition {
+    /// Direction towards the sun, +Y is up.
+    Direction(Vec3),
+    /// Position of the sun at a local solar time, on a map where -Z is north
+    /// and +X is east.
+    Time {
+        day_of_year: u32,
+        hour: f64,
+        latitude: f64,
+    },
+}
+
+impl SunPosition {
+    pub fn direction(&self) -> Vec3 {
+        match *self {
+            SunPosition::Direction(direction) => direction.unit_vector(),
+            SunPosition::Time {
+                day_of_year,
+                hour,
+                latitude,
+            } => {
+                let declination = util::degrees_to_radians(-23.44)
+                    * (2.0 * PI / 365.0 * (day_of_year as f64 + 10.0)).cos();
+                let hour_angle = util::degrees_to_radians(15.0 * (hour - 12.0));
+                let latitude = util::degrees_to_radians(latitude);
+
+                let east = -declination.cos() * hour_angle.sin();
+                let north = latitude.cos() * declination.sin()
+                    - latitude.sin() * declination.cos() * hour_angle.cos();
+                let up = latitude.sin() * declination.sin()
+                    + latitude.cos() * declination.cos() * hour_angle.cos();
+                Vec3::new(east, up, -north)
+            }
+        }
+    }
+}
+
+/// Perez sky luminance distribution coefficients A to E as linear functions
+/// `[slope, offset]` of turbidity, for Y, x and y (Preetham et al. 1999).
+static PEREZ: [[[f64; 2]; 5]; 3] = [
+    [
+        [0.1787, -1.4630],
+        [-0.3554, 0.4275],
+        [-0.0227, 5.3251],
+        [0.1206, -2.5771],
+        [-0.0670, 0.3703],
+    ],
+    [
+        [-0.0193, -0.2592],
+        [-0.0665, 0.0008],
+        [-0.0004, 0.2125],
+        [-0.0641, -0.8989],
+        [-0.0033, 0.0452],
+    ],
+    [
+        [-0.0167, -0.2608],
+        [-0.0950, 0.0092],
+        [-0.0079, 0.2102],
+        [-0.0441, -1.6537],
+        [-0.0109, 0.0529],
+    ],
+];
+
+/// Zenith chromaticity x and y as polynomials in turbidity (rows T², T, 1)
+/// and sun zenith angle (columns θ³, θ², θ, 1).
+static ZENITH_CHROMATICITY: [[[f64; 4]; 3]; 2] = [
+    [
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ],
+    [
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ],
+];
+
+/// Clear sky brightness and colour from Preetham's fit of the Perez model.
+struct PerezSky {
+    sun_direction: Vec3,
+    /// Perez coefficients for Y, x and y.
+    perez: [[f64; 5]; 3],
+    /// Zenith Y, x and y, each divided by its Perez function at the zenith.
+    zenith: [f64; 3],
+}
+
+impl PerezSky {
+    /// `sun_theta` is the sun's zenith angle, kept above the horizon.
+    fn new(sun_direction: Vec3, sun_theta: f64, turbidity: f64) -> PerezSky {
+        let mut perez = [[0.0; 5]; 3];
+        for (channel, coefficients) in perez.iter_mut().zip(PEREZ.iter()) {
+            for (value, [slope, offset]) in channel.iter_mut().zip(coefficients.iter()) {
+                *value = slope * turbidity + offset;
+            }
+        }
+
+        let chi = (4.0 / 9.0 - turbidity / 120.0) * (PI - 2.0 * sun_theta);
+        let zenith_luminance = 1000.0
+            * ((4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192)
+            * LUMINANCE_SCALE;
+        let zenith_values = [
+            zenith_luminance,
+            zenith_chromaticity(&ZENITH_CHROMATICITY[0], turbidity, sun_theta),
+            zenith_chromaticity(&ZENITH_CHROMATICITY[1], turbidity, sun_theta),
+        ];
+        let mut zenith = [0.0; 3];
+        for i in 0..3 {
+            zenith[i] = zenith_values[i] / perez_function(&perez[i], 1.0, sun_theta.cos());
+        }
+
+        PerezSky {
+            sun_direction,
+            perez,
+            zenith,
+        }
+    }
+
+    /// Radiance in a unit direction above the horizon, zero below it.
+    fn radiance(&self, direction: Vec3) -> Color {
+        if direction.y < 0.0 {
+            return Color::default();
+        }
+        let cos_theta = direction.y.max(1e-3);
+        let cos_gamma = util::clamp(direction.dot(self.sun_direction), -1.0, 1.0);
+        let mut values = [0.0; 3];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.zenith[i] * perez_function(&self.perez[i], cos_theta, cos_gamma);
+        }
+        let [luminance, x, y] = values;
+        xyy_to_rgb(x, y, luminance)
+    }
+}
+
+/// Preetham's analytic daylight sky with a sun disk whose colour follows
+/// from the air it passes through. Directions below the horizon see a
+/// diffuse ground lit by both.
+pub struct Sky {
+    model: PerezSky,
+    sun_direction: Vec3,
+    sun_radiance: Color,
+    cos_sun_radius: f64,
+    ground_radiance: Color,
+    intensity: f64,
+    /// Tabulated sky without the sun disk, used to sample directions.
+    table: EnvironmentMap,
+    /// Fraction of light samples aimed at the sun disk.
+    sun_probability: f64,
+}
+
+impl Sky {
+    pub fn new(settings: &SkySettings, intensity: f64) -> Sky {
+        let sun_direction = settings.sun.direction();
+        let turbidity = util::clamp(settings.turbidity, 1.7, 10.0);
+        // The model only holds for a sun above the horizon
+        let sun_theta = sun_direction.y.max(0.0).acos().min(0.5 * PI - 1e-3);
+        let model = PerezSky::new(sun_direction, sun_theta, turbidity);
+
+        let cos_sun_radius = SUN_ANGULAR_RADIUS.cos();
+        let sun_solid_angle = 2.0 * PI * (1.0 - cos_sun_radius);
+        let sun_radiance = if sun_direction.y > 0.0 {
+            let transmittance = sun_transmittance(sun_theta, turbidity);
+            SOLAR_ILLUMINANCE / sun_solid_angle * LUMINANCE_SCALE * transmittance
+        } else {
+            Color::default()
+        };
+
+        // Tabulate the upper hemisphere, gathering the light it sheds on the
+        // ground on the way
+        let mut pixels = Vec::with_capacity(TABLE_WIDTH * TABLE_HEIGHT);
+        let sun_power = sun_radiance * sun_solid_angle;
+        let mut irradiance = sun_power * sun_direction.y.max(0.0);
+        let pixel_solid_angle = 2.0 * PI * PI / (TABLE_WIDTH * TABLE_HEIGHT) as f64;
+        for y in 0..TABLE_HEIGHT {
+            let v = (y as f64 + 0.5) / TABLE_HEIGHT as f64;
+            for x in 0..TABLE_WIDTH {
+                let u = (x as f64 + 0.5) / TABLE_WIDTH as f64;
+                let direction = environment::direction_from_uv(u, v, 0.0);
+                let radiance = model.radiance(direction);
+                let sin_theta = (v * PI).sin();
+                irradiance =
+                    irradiance + radiance * (direction.y.max(0.0) * sin_theta * pixel_solid_angle);
+                pixels.push(radiance);
+            }
+        }
+        let ground_radiance = settings.ground_albedo * irradiance / PI;
+        let mut sky_power = Color::default();
+        for (i, pixel) in pixels.iter_mut().enumerate() {
+            if i >= TABLE_WIDTH * TABLE_HEIGHT / 2 {
+                *pixel = ground_radiance;
+            }
+            let sin_theta = (PI * ((i / TABLE_WIDTH) as f64 + 0.5) / TABLE_HEIGHT as f64).sin();
+            sky_power = sky_power + *pixel * (sin_theta * pixel_solid_angle);
+        }
+        let table = EnvironmentMap::new(
+            HdrImage {
+                width: TABLE_WIDTH,
+                height: TABLE_HEIGHT,
+                pixels,
+            },
+            0.0,
+            1.0,
+        );
+
+        Sky {
+            model,
+            sun_direction,
+            sun_radiance,
+            cos_sun_radius,
+            ground_radiance,
+            intensity,
+            table,
+            // Light samples are shared in proportion to how much light the
+            // sun and the rest of the sky send out
+            sun_probability: luminance(sun_power) / luminance(sun_power + sky_power),
+        }
+    }
+
+    pub fn radiance(&self, direction: Vec3) -> Color {
+        let direction = direction.unit_vector();
+        if direction.y < 0.0 {
+            return self.intensity * self.ground_radiance;
+        }
+        let mut radiance = self.model.radiance(direction);
+        if direction.dot(self.sun_direction) >= self.cos_sun_radius {
+            radiance = radiance + self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    pub fn sample(&self, u: [f64; 2]) -> Option<(Vec3, Color, f64)> {
+        let direction = if u[0] < self.sun_probability {
+            let u0 = u[0] / self.sun_probability;
+            let local = frame::sample_cone(u0, u[1], self.cos_sun_radius);
+            Frame::from_normal(self.sun_direction).to_world(local)
+        } else {
+            let u0 = (u[0] - self.sun_probability) / (1.0 - self.sun_probability);
+            self.table.sample([u0, u[1]])?.0
+        };
+        let pdf = self.pdf(direction);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some((direction, self.radiance(direction), pdf))
+    }
+
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        let direction = direction.unit_vector();
+        let mut pdf = (1.0 - self.sun_probability) * self.table.pdf(direction);
+        if direction.dot(self.sun_direction) >= self.cos_sun_radius {
+            pdf += self.sun_probability * frame::cone_pdf(self.cos_sun_radius);
+        }
+        pdf
+    }
+}
+
+/// Relative sky brightness at zenith angle `theta` and angle `gamma` from
+/// the sun, given by their cosines.
+fn perez_function(coefficients: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    let gamma = cos_gamma.acos();
+    (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+}
+
+fn zenith_chromaticity(matrix: &[[f64; 4]; 3], turbidity: f64, sun_theta: f64) -> f64 {
+    let t = [turbidity * turbidity, turbidity, 1.0];
+    let theta = [sun_theta.powi(3), sun_theta.powi(2), sun_theta, 1.0];
+    let mut value = 0.0;
+    for (row, t) in matrix.iter().zip(t.iter()) {
+        for (coefficient, theta) in row.iter().zip(theta.iter()) {
+            value += t * coefficient * theta;
+        }
+    }
+    value
+}
+
+/// Fraction of sunlight that reaches the ground at each channel's
+/// wavelength, from Rayleigh and aerosol scattering along the air mass
+/// between the sun and the ground.
+fn sun_transmittance(sun_theta: f64, turbidity: f64) -> Color {
+    let zenith_degrees = sun_theta.to_degrees();
+    let air_mass = 1.0 / (sun_theta.cos() + 0.15 * (93.885 - zenith_degrees).powf(-1.253));
+    let beta = 0.046_083_66 * turbidity - 0.045_860_26;
+    let [r, g, b] = CHANNEL_WAVELENGTHS.map(|lambda: f64| {
+        let rayleigh = (-0.008_735 * lambda.powf(-4.08) * air_mass).exp();
+        let aerosol = (-beta * lambda.powf(-1.3) * air_mass).exp();
+        rayleigh * aerosol
+    });
+    Color::new(r, g, b)
+}
+
+/// Converts CIE xyY to linear sRGB, clamping colours outside the gamut.
+pub fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> Color {
+    if y <= 0.0 {
+        return Color::default();
+    }
+    let big_x = x * luminance / y;
+    let big_z = (1.0 - x - y) * luminance / y;
+    let big_y = luminance;
+    Color::new(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+    )
+}
+
+#[test]
+fn sun_position_test() {
+    // Solar noon at the March equinox, the sun is due south at an elevation
+    // of 90 degrees minus the latitude
+    let direction = SunPosition::Time {
+        day_of_year: 79,
+        hour: 12.0,
+        latitude: 50.0,
+    }
+    .direction();
+    assert!((direction.y.asin().to_degrees() - 40.0).abs() < 1.0);
+    assert!(direction.x.abs() < 1e-9 && direction.z > 0.0);
+
+    // Afternoon sun is in the west
+    let afternoon = SunPosition::Time {
+        day_of_year: 79,
+        hour: 16.0,
+        latitude: 50.0,
+    }
+    .direction();
+    assert!(afternoon.x < 0.0);
+}
+
+#[test]
+fn sky_sampling_test() {
+    let sky = Sky::new(&SkySettings::default(), 1.0);
+
+    // The sun outshines the sky and is reddened by the atmosphere
+    let sun = sky.radiance(sky.sun_direction);
+    let zenith = sky.radiance(Vec3::new(0.0, 1.0, 0.0));
+    assert!(sun.y > 1000.0 * zenith.y);
+    assert!(sun.x > sun.z);
+
+    for i in 0..100 {
+        let u = [i as f64 / 100.0, (i * 37 % 100) as f64 / 100.0];
+        let (direction, radiance, pdf) = sky.sample(u).unwrap();
+        assert_eq!(radiance, sky.radiance(direction));
+        assert!((pdf - sky.pdf(direction)).abs() < 1e-9 * pdf);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::material::MATERIAL_NAMES;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters collected while rendering. They are global so that hit tests
+/// deep inside the `Hittable` implementations can record work without the
+/// counters being threaded through every call.
+#[derive(Clone, Copy)]
+pub enum Counter {
+    PrimaryRays,
+    RaysTraced,
+    IntersectionTests,
+    /// Nodes of the light BVH visited while picking lights. Objects are
+    /// tested in a flat list, which has no tree to count.
+    LightBvhNodes,
+    MaxDepthTerminations,
+    RouletteTerminations,
+}
+
+const COUNTER_COUNT: usize = 6;
+static COUNTERS: [AtomicU64; COUNTER_COUNT] = [const { AtomicU64::new(0) }; COUNTER_COUNT];
+static SCATTER_COUNTS: [AtomicU64; MATERIAL_NAMES.len()] =
+    [const { AtomicU64::new(0) }; MATERIAL_NAMES.len()];
+
+pub fn increment(counter: Counter) {
+    COUNTERS[counter as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_scatter(material_kind: usize) {
+    SCATTER_COUNTS[material_kind].fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn get(counter: Counter) -> u64 {
+    COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+    Silent,
+}
+
+impl StatsFormat {
+    pub fn parse(name: &str) -> Option<StatsFormat> {
+        match name {
+            "text" => Some(StatsFormat::Text),
+            "json" => Some(StatsFormat::Json),
+            "none" => Some(StatsFormat::Silent),
+            _ => None,
+        }
+    }
+}
+
+/// Point in time copy of all counters.
+pub struct RenderStats {
+    pub primary_rays: u64,
+    pub secondary_rays: u64,
+    pub intersection_tests: u64,
+    pub light_bvh_nodes: u64,
+    pub max_depth_terminations: u64,
+    pub roulette_terminations: u64,
+    pub scatter_counts: [u64; MATERIAL_NAMES.len()],
+}
+
+impl RenderStats {
+    pub fn snapshot() -> RenderStats {
+        let primary_rays = get(Counter::PrimaryRays);
+        let mut scatter_counts = [0; MATERIAL_NAMES.len()];
+        for (count, atomic) in scatter_counts.iter_mut().zip(SCATTER_COUNTS.iter()) {
+            *count = atomic.load(Ordering::Relaxed);
+        }
+
+        RenderStats {
+            primary_rays,
+            secondary_rays: get(Counter::RaysTraced).saturating_sub(primary_rays),
+            intersection_tests: get(Counter::IntersectionTests),
+            light_bvh_nodes: get(Counter::LightBvhNodes),
+            max_depth_terminations: get(Counter::MaxDepthTerminations),
+            roulette_terminations: get(Counter::RouletteTerminations),
+            scatter_counts,
+        }
+    }
+
+    /// Average number of ray segments per camera path.
+    pub fn average_path_length(&self) -> f64 {
+        if self.primary_rays == 0 {
+            return 0.0;
+        }
+        (self.primary_rays + self.secondary_rays) as f64 / self.primary_rays as f64
+    }
+
+    pub fn print(&self, format: StatsFormat) {
+        match format {
+            StatsFormat::Text => self.print_text(),
+            StatsFormat::Json => self.print_json(),
+            StatsFormat::Silent => {}
+        }
+    }
+
+    fn print_text(&self) {
+        eprintln!("Render statistics");
+        eprintln!("  Primary rays:            {}", self.primary_rays);
+        eprintln!("  Secondary rays:          {}", self.secondary_rays);
+        eprintln!("  Intersection tests:      {}", self.intersection_tests);
+        eprintln!("  Light BVH nodes visited: {}", self.light_bvh_nodes);
+        eprintln!(
+            "  Average path length:     {:.3}",
+            self.average_path_length()
+        );
+        eprintln!("  Paths cut at max depth:  {}", self.max_depth_terminations);
+        eprintln!("  Paths cut by roulette:   {}", self.roulette_terminations);
+        eprintln!("  Scatter events");
+        for (name, count) in MATERIAL_NAMES.iter().zip(self.scatter_counts.iter()) {
+            eprintln!("    {:<22} {}", format!("{}:", name), count);
+        }
+    }
+
+    fn print_json(&self) {
+        let scatters: Vec<String> = MATERIAL_NAMES
+            .iter()
+            .zip(self.scatter_counts.iter())
+            .map(|(name, count)| format!("\"{}\":{}", name, count))
+            .collect();
+
+        eprintln!(
+            "{{\"primary_rays\":{},\"secondary_rays\":{},\"intersection_tests\":{},\"light_bvh_nodes\":{},\"average_path_length\":{:.4},\"max_depth_terminations\":{},\"roulette_terminations\":{},\"scatter_counts\":{{{}}}}}",
+            self.primary_rays,
+            self.secondary_rays,
+            self.intersection_tests,
+            self.light_bvh_nodes,
+            self.average_path_length(),
+            self.max_depth_terminations,
+            self.roulette_terminations,
+            scatters.join(",")
+        );
+    }
+}
+
+#[test]
+fn average_path_length_test() {
+    let stats = RenderStats {
+        primary_rays: 4,
+        secondary_rays: 6,
+        intersection_tests: 0,
+        light_bvh_nodes: 0,
+        max_depth_terminations: 0,
+        roulette_terminations: 0,
+        scatter_counts: [0; MATERIAL_NAMES.len()],
+    };
+
+    assert_eq!(2.5, stats.average_path_length());
+}
@@ -0,0 +1,160 @@
+use crate::util;
+use crate::vector::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    Clamp,
+    Reinhard,
+    /// Reinhard with a white point: radiance of `white` maps to 1.0.
+    ExtendedReinhard {
+        white: f64,
+    },
+    /// John Hable's Uncharted 2 filmic curve.
+    Filmic,
+    /// Stephen Hill's fit of the ACES reference and output transforms.
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub fn parse(name: &str) -> Option<ToneMapOperator> {
+        match name {
+            "clamp" => Some(ToneMapOperator::Clamp),
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "reinhard-extended" => Some(ToneMapOperator::ExtendedReinhard { white: 4.0 }),
+            "filmic" => Some(ToneMapOperator::Filmic),
+            "aces" => Some(ToneMapOperator::Aces),
+            _ => None,
+        }
+    }
+}
+
+/// Turns scene-referred linear radiance into display-encoded sRGB values.
+#[derive(Clone, Copy)]
+pub struct ToneMapper {
+    pub operator: ToneMapOperator,
+    /// Exposure adjustment in stops, each stop doubles the radiance.
+    pub exposure: f64,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        ToneMapper {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+        }
+    }
+}
+
+impl ToneMapper {
+    /// Applies exposure, the tone curve and the sRGB transfer function.
+    /// The result is in the range 0.0 <= x <= 1.0.
+    pub fn map(&self, radiance: Color) -> Color {
+        let exposed = radiance * f64::powf(2.0, self.exposure);
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => exposed,
+            ToneMapOperator::Reinhard => map_channels(exposed, |x| x / (1.0 + x)),
+            ToneMapOperator::ExtendedReinhard { white } => {
+                map_channels(exposed, |x| x * (1.0 + x / (white * white)) / (1.0 + x))
+            }
+            ToneMapOperator::Filmic => {
+                let white_scale = 1.0 / hable_partial(HABLE_WHITE);
+                map_channels(exposed, |x| {
+                    hable_partial(x * HABLE_EXPOSURE_BIAS) * white_scale
+                })
+            }
+            ToneMapOperator::Aces => aces_fitted(exposed),
+        };
+
+        map_channels(mapped, |x| srgb_oetf(util::clamp(x, 0.0, 1.0)))
+    }
+}
+
+fn map_channels(color: Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x), f(color.y), f(color.z))
+}
+
+/// The piecewise sRGB opto-electronic transfer function (IEC 61966-2-1).
+pub fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+static HABLE_WHITE: f64 = 11.2;
+static HABLE_EXPOSURE_BIAS: f64 = 2.0;
+
+fn hable_partial(x: f64) -> f64 {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+fn aces_fitted(color: Color) -> Color {
+    // sRGB => XYZ => D65_2_D60 => AP1 => RRT_SAT
+    let input = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    // ODT_SAT => XYZ => D60_2_D65 => sRGB
+    let output = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let v = mul_matrix(&input, color);
+    let fitted = map_channels(v, |x| {
+        let a = x * (x + 0.0245786) - 0.000090537;
+        let b = x * (0.983729 * x + 0.4329510) + 0.238081;
+        a / b
+    });
+    mul_matrix(&output, fitted)
+}
+
+fn mul_matrix(m: &[[f64; 3]; 3], v: Color) -> Color {
+    Color::new(
+        m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+        m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+        m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+    )
+}
+
+#[test]
+fn srgb_oetf_test() {
+    assert_eq!(0.0, srgb_oetf(0.0));
+    assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-12);
+    // Linear below the breakpoint, a power curve above it
+    assert!((srgb_oetf(0.003) - 12.92 * 0.003).abs() < 1e-12);
+    assert!((srgb_oetf(0.5) - 0.735_356_983).abs() < 1e-8);
+    // Both pieces meet at the breakpoint
+    assert!((srgb_oetf(0.003_130_7) - srgb_oetf(0.003_130_9)).abs() < 1e-5);
+}
+
+#[test]
+fn tone_map_operators_range_test() {
+    let operators = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::ExtendedReinhard { white: 4.0 },
+        ToneMapOperator::Filmic,
+        ToneMapOperator::Aces,
+    ];
+
+    for operator in operators {
+        let tone_mapper = ToneMapper {
+            operator,
+            exposure: 0.0,
+        };
+        let black = tone_mapper.map(Color::default());
+        let bright = tone_mapper.map(Color::new(100.0, 100.0, 100.0));
+        assert!(black.max_component() < 1e-3);
+        assert!(bright.x <= 1.0 && bright.x > 0.9);
+    }
+}
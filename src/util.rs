@@ -1,4 +1,6 @@
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
 
 pub static INFINITY: f64 = f64::INFINITY;
 pub static PI: f64 = std::f64::consts::PI;
@@ -7,16 +9,31 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
 
-pub fn random_double() -> f64 {
-    let mut rng = rand::thread_rng();
+thread_local! {
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
 
-    rng.gen()
+/// Restarts the random sequence so that a render can be reproduced.
+pub fn seed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
 }
 
-pub fn random_double_rng(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
+/// Seed for one pixel, so a single pixel can be traced again in isolation
+/// and take exactly the same paths as in the full render.
+pub fn pixel_seed(seed: u64, x: usize, y: usize) -> u64 {
+    // SplitMix64 finaliser over the combined inputs
+    let mut z = seed ^ ((x as u64) << 32 | y as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
 
-    rng.gen_range(min..=max)
+pub fn random_double() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+pub fn random_double_rng(min: f64, max: f64) -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen_range(min..=max))
 }
 
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
@@ -27,5 +44,5 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
         return max;
     }
 
-    return x;
+    x
 }
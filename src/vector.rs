@@ -22,7 +22,7 @@ impl Vec3 {
     }
 
     pub fn dot(self, rhs: Vec3) -> f64 {
-        return self.x * rhs.x + self.y * rhs.y + self.z * rhs.z;
+        self.x * rhs.x + self.y * rhs.y + self.z * rhs.z
     }
 
     pub fn cross(self, rhs: Vec3) -> Vec3 {
@@ -33,6 +33,10 @@ impl Vec3 {
         }
     }
 
+    pub fn max_component(self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
     pub fn unit_vector(self) -> Vec3 {
         self / self.length()
     }
@@ -73,14 +77,14 @@ impl Vec3 {
     }
 
     pub fn random_unit_vector() -> Vec3 {
-        return Vec3::random_in_unit_sphere().unit_vector();
+        Vec3::random_in_unit_sphere().unit_vector()
     }
 
     pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
         let cos_theta = -uv.dot(n).min(1.0);
         let r_out_perp: Vec3 = etai_over_etat * (uv + n * cos_theta);
         let r_out_parallel: Vec3 = -f64::sqrt((1.0 - r_out_perp.length_squared()).abs()) * n;
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
     }
 
     pub fn random_in_unit_disk() -> Vec3 {
@@ -101,6 +105,11 @@ impl Vec3 {
 pub type Point3 = Vec3;
 pub type Color = Vec3;
 
+/// Brightness of a linear Rec. 709 colour.
+pub fn luminance(color: Color) -> f64 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
 impl ops::Add<Vec3> for Vec3 {
     type Output = Vec3;
 
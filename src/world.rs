@@ -1,12 +1,63 @@
-use crate::hittable::{HittableList, Sphere};
+use crate::blackbody::Blackbody;
+use crate::environment::Environment;
+use crate::hittable::{HittableList, Quad, Sphere, Triangle};
+use crate::ies;
+use crate::light::Light;
+use crate::lightsampler::{LightSampler, LightSampling};
 use crate::material::MaterialEnum;
+use crate::principled::Principled;
+use crate::shape::Shape;
 use crate::util::{random_double, random_double_rng};
 use crate::vector::{Color, Point3, Vec3};
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufRead, BufReader};
 use std::io;
+use std::io::{BufRead, BufReader};
+
+/// Everything a path can interact with.
+pub struct Scene {
+    pub world: HittableList,
+    pub environment: Environment,
+    pub lights: Vec<Light>,
+    pub light_sampler: LightSampler,
+    /// Index in `lights` of each emissive object.
+    area_lights: HashMap<usize, usize>,
+}
+
+impl Scene {
+    pub fn new(
+        world: HittableList,
+        environment: Environment,
+        lights: Vec<Light>,
+        light_sampling: LightSampling,
+    ) -> Scene {
+        let area_lights = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(i, light)| match light {
+                Light::Area { object_id, .. } => Some((*object_id, i)),
+                _ => None,
+            })
+            .collect();
+        Scene {
+            world,
+            environment,
+            light_sampler: LightSampler::new(&lights, light_sampling),
+            lights,
+            area_lights,
+        }
+    }
 
-pub fn load_world_file() -> Result<HittableList, io::Error> {
+    /// Density of light sampling picking a direction from a surface at `p`
+    /// facing `n` that reaches the emissive object `object_id`.
+    pub fn light_pdf(&self, object_id: usize, p: Point3, n: Vec3) -> f64 {
+        self.area_lights.get(&object_id).map_or(0.0, |light| {
+            self.light_sampler.pmf(p, n, *light) * self.lights[*light].pdf(p)
+        })
+    }
+}
+
+pub fn load_world_file() -> Result<(HittableList, Vec<Light>), io::Error> {
     let path = "world.txt";
 
     let input = File::open(path)?;
@@ -15,33 +66,58 @@ pub fn load_world_file() -> Result<HittableList, io::Error> {
     let mut world = HittableList {
         objects: Vec::new(),
     };
+    let mut lights = Vec::new();
+    let mut entry = String::new();
+    let mut light_kind = String::new();
     let mut radius = 0.0;
-    let mut center = Vec3::default();
+    let mut points = Vec::new();
     let mut material_num: i32 = -1;
     let mut material: MaterialEnum;
     for (i, line) in buffered.lines().enumerate().map(|(i, l)| (i, l.unwrap())) {
         // Comment
-        // Radius
-        // Center
-        // Material num
-        // Material albedo color (or a special param)
+        // Radius, or `quad`, `triangle` or `light`
+        // Center, the corner and two edges of a quad, the corners of a triangle,
+        // or the position or direction of a light
+        // Material num, or the kind of light
+        // Material albedo color (or a special param), or the light's colour and params
         match i % 5 {
             0 => continue,
-            1 => radius = parse_radius(&line),
+            1 => {
+                entry = match line.as_str() {
+                    "quad" | "triangle" | "light" => line,
+                    _ => {
+                        radius = parse_radius(&line);
+                        String::from("sphere")
+                    }
+                };
+            }
             2 => {
-                center = parse_center(&line);
+                points = parse_points(&line);
             }
+            3 if entry == "light" => light_kind = line,
             3 => {
                 material_num = parse_material_num(&line);
             }
+            4 if entry == "light" => lights.push(parse_light(&light_kind, points[0], &line)),
             4 => {
-                material = parse_material(&line, material_num);
-                let sphere = Sphere {
-                    radius,
-                    center,
-                    material,
+                let shape = match entry.as_str() {
+                    "quad" => Shape::Quad {
+                        corner: points[0],
+                        u: points[1],
+                        v: points[2],
+                    },
+                    "triangle" => Shape::Triangle {
+                        a: points[0],
+                        b: points[1],
+                        c: points[2],
+                    },
+                    _ => Shape::Sphere {
+                        center: points[0],
+                        radius,
+                    },
                 };
-                world.objects.push(Box::new(sphere));
+                material = parse_material(&line, material_num, &shape);
+                add_object(&mut world, &mut lights, shape, material);
             }
             _ => {
                 panic!("Should not get here")
@@ -49,78 +125,231 @@ pub fn load_world_file() -> Result<HittableList, io::Error> {
         }
     }
 
-    Ok(world)
+    Ok((world, lights))
+}
+
+/// Adds an object to the world, and to the lights when it is emissive.
+pub fn add_object(
+    world: &mut HittableList,
+    lights: &mut Vec<Light>,
+    shape: Shape,
+    material: MaterialEnum,
+) {
+    if let MaterialEnum::Emissive { radiance } = material {
+        lights.push(Light::Area {
+            shape,
+            radiance,
+            object_id: world.objects.len(),
+        });
+    }
+    match shape {
+        Shape::Sphere { center, radius } => world.objects.push(Box::new(Sphere {
+            center,
+            radius,
+            material,
+        })),
+        Shape::Quad { corner, u, v } => world.objects.push(Box::new(Quad {
+            corner,
+            u,
+            v,
+            material,
+        })),
+        Shape::Triangle { a, b, c } => world.objects.push(Box::new(Triangle { a, b, c, material })),
+    }
 }
 
-fn parse_material_num(line: &String) -> i32 {
+fn parse_material_num(line: &str) -> i32 {
     line.parse().unwrap()
 }
 
-fn parse_material(line: &String, material_num: i32) -> MaterialEnum {
+fn parse_material(line: &str, material_num: i32, shape: &Shape) -> MaterialEnum {
     match material_num {
-        1 => {
-            return MaterialEnum::Lambertian {
-                albedo: parse_color(line),
-            }
-        }
-        2 => {
-            let (albedo, fuzziness) = parse_color_with_fuzziness(line);
-            return MaterialEnum::Metal { albedo, fuzziness };
-        }
+        1 => MaterialEnum::Lambertian {
+            albedo: parse_color(line),
+        },
+        2 => parse_metal(line),
         3 => {
             let index_of_refraction = parse_index_of_refraction(line);
-            return MaterialEnum::Dielectric {
+            MaterialEnum::Dielectric {
                 index_of_refraction,
-            };
+            }
         }
+        4 => parse_rough_dielectric(line),
+        5 => parse_principled(line),
+        6 => MaterialEnum::Emissive {
+            radiance: parse_emission(line, shape),
+        },
         _ => {
             panic!("Should not get here")
         }
     }
 }
 
-fn parse_center(line: &String) -> Point3 {
+fn parse_center(line: &str) -> Point3 {
     let point3_values: Vec<&str> = line.split(' ').to_owned().collect();
-    return Point3::new(
+    Point3::new(
         point3_values[0].parse().unwrap(),
         point3_values[1].parse().unwrap(),
         point3_values[2].parse().unwrap(),
-    );
+    )
 }
 
-fn parse_color(line: &String) -> Color {
+/// One or more points given as consecutive `x y z` triples.
+fn parse_points(line: &str) -> Vec<Point3> {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    values
+        .chunks(3)
+        .map(|point| parse_center(&point.join(" ")))
+        .collect()
+}
+
+fn parse_color(line: &str) -> Color {
     let color_values: Vec<&str> = line.split(' ').to_owned().collect();
-    return Color::new(
+    Color::new(
         color_values[0].parse().unwrap(),
         color_values[1].parse().unwrap(),
         color_values[2].parse().unwrap(),
-    );
+    )
 }
 
-fn parse_color_with_fuzziness(line: &String) -> (Color, f64) {
-    let color_values: Vec<&str> = line.split(' ').to_owned().collect();
-    let fuzzy = color_values[3].parse().unwrap();
-    assert!(fuzzy >= 0.0 && fuzzy <= 1.0);
-    return (
-        Color::new(
-            color_values[0].parse().unwrap(),
-            color_values[1].parse().unwrap(),
-            color_values[2].parse().unwrap(),
-        ),
-        fuzzy,
-    );
+/// Either `r g b roughness [anisotropy]`, where the colour is the
+/// reflectance at normal incidence, or `preset roughness [anisotropy]`.
+fn parse_metal(line: &str) -> MaterialEnum {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    let (preset, params) = match values[0].parse::<f64>() {
+        Ok(_) => (None, &values[3..]),
+        Err(_) => (Some(values[0]), &values[1..]),
+    };
+    let roughness: f64 = params[0].parse().unwrap();
+    assert!((0.0..=1.0).contains(&roughness));
+    let anisotropy: f64 = params.get(1).map_or(0.0, |value| value.parse().unwrap());
+    assert!((0.0..=1.0).contains(&anisotropy));
+
+    match preset {
+        Some(name) => MaterialEnum::metal_preset(name, roughness, anisotropy)
+            .unwrap_or_else(|| panic!("Unknown metal '{}'", name)),
+        None => MaterialEnum::metal(parse_color(line), roughness, anisotropy),
+    }
 }
 
-fn parse_radius(line: &String) -> f64 {
-    return line.parse().unwrap();
+/// `ior roughness [r g b]`, the optional colour being the absorption
+/// coefficient per unit of distance travelled inside.
+fn parse_rough_dielectric(line: &str) -> MaterialEnum {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    let roughness: f64 = values[1].parse().unwrap();
+    assert!((0.0..=1.0).contains(&roughness));
+    let absorption = if values.len() > 2 {
+        parse_color(&values[2..].join(" "))
+    } else {
+        Color::default()
+    };
+
+    MaterialEnum::RoughDielectric {
+        index_of_refraction: values[0].parse().unwrap(),
+        roughness,
+        absorption,
+    }
+}
+
+/// `r g b` base colour followed by metallic, roughness, specular, specular
+/// tint, sheen, clearcoat, transmission and ior. Trailing parameters can be
+/// left out and take their default value.
+fn parse_principled(line: &str) -> MaterialEnum {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    let defaults = [0.0, 0.5, 0.5, 0.0, 0.0, 0.0, 0.0, 1.5];
+    let params: Vec<f64> = defaults
+        .iter()
+        .enumerate()
+        .map(|(i, default)| values.get(i + 3).map_or(*default, |v| v.parse().unwrap()))
+        .collect();
+    assert!(params[..7].iter().all(|p| (0.0..=1.0).contains(p)));
+
+    MaterialEnum::Principled(Principled {
+        base_color: parse_color(line),
+        metallic: params[0],
+        roughness: params[1],
+        specular: params[2],
+        specular_tint: params[3],
+        sheen: params[4],
+        clearcoat: params[5],
+        transmission: params[6],
+        ior: params[7],
+    })
+}
+
+/// Either `r g b [intensity]`, or a colour temperature such as `2700K`
+/// followed by an intensity, the luminous flux in lumens as `800lm` or the
+/// radiated power as `60W`.
+fn parse_emission(line: &str, shape: &Shape) -> Color {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    let Some(temperature) = values[0].strip_suffix('K') else {
+        let intensity: f64 = values.get(3).map_or(1.0, |v| v.parse().unwrap());
+        return parse_color(line) * intensity;
+    };
+    let blackbody = Blackbody::new(temperature.parse().unwrap());
+    match values.get(1) {
+        None => blackbody.color,
+        Some(value) => {
+            if let Some(lumens) = value.strip_suffix("lm") {
+                blackbody.radiance_for_lumens(lumens.parse().unwrap(), shape.area())
+            } else if let Some(watts) = value.strip_suffix('W') {
+                blackbody.radiance_for_watts(watts.parse().unwrap(), shape.area())
+            } else {
+                blackbody.color * value.parse::<f64>().unwrap()
+            }
+        }
+    }
 }
 
-fn parse_index_of_refraction(line: &String) -> f64 {
-    return line.parse().unwrap();
+/// `r g b intensity`, where the colour times the intensity is the radiant
+/// intensity of a point or spot light or the irradiance of a directional
+/// one. Spots continue with `x y z outer_angle [inner_angle [profile.ies]]`,
+/// the direction they point in and the cone angles in degrees.
+fn parse_light(kind: &str, point: Point3, line: &str) -> Light {
+    let values: Vec<&str> = line.split(' ').to_owned().collect();
+    let power = parse_color(line) * values[3].parse::<f64>().unwrap();
+    match kind {
+        "point" => Light::Point {
+            position: point,
+            intensity: power,
+        },
+        "spot" => {
+            let outer_angle: f64 = values[7].parse().unwrap();
+            let inner_angle = values.get(8).map_or(outer_angle, |v| v.parse().unwrap());
+            let profile = values.get(9).map(|path| {
+                ies::load_ies(path)
+                    .unwrap_or_else(|err| panic!("Could not load profile '{}': {}", path, err))
+            });
+            Light::spot(
+                point,
+                parse_center(&values[4..7].join(" ")),
+                power,
+                outer_angle,
+                inner_angle,
+                profile,
+            )
+        }
+        "directional" => Light::Directional {
+            direction: point,
+            irradiance: power,
+        },
+        _ => panic!("Unknown light '{}'", kind),
+    }
 }
 
-pub fn random_scene() -> HittableList {
+fn parse_radius(line: &str) -> f64 {
+    line.parse().unwrap()
+}
+
+fn parse_index_of_refraction(line: &str) -> f64 {
+    line.parse().unwrap()
+}
+
+/// The cover scene of Ray Tracing in One Weekend. With `string_lights`
+/// strands of small glowing bulbs hang above the spheres.
+pub fn random_scene(string_lights: bool) -> (HittableList, Vec<Light>) {
     let mut world = HittableList { objects: vec![] };
+    let mut lights = Vec::new();
 
     let ground_color = MaterialEnum::Lambertian {
         albedo: Color::new(0.5, 0.5, 0.5),
@@ -156,10 +385,7 @@ pub fn random_scene() -> HittableList {
                     // metal
                     let albedo = Color::random_with_limit(0.5, 1.0);
                     let fuzz = random_double_rng(0.0, 0.5);
-                    let sphere_material = MaterialEnum::Metal {
-                        albedo,
-                        fuzziness: fuzz,
-                    };
+                    let sphere_material = MaterialEnum::metal(albedo, fuzz, 0.0);
                     world.objects.push(Box::new(Sphere {
                         center,
                         radius: 0.2,
@@ -197,10 +423,7 @@ pub fn random_scene() -> HittableList {
             material: material2,
         }));
 
-        let material3 = MaterialEnum::Metal {
-            albedo: Color::new(0.7, 0.6, 0.5),
-            fuzziness: 0.0,
-        };
+        let material3 = MaterialEnum::metal(Color::new(0.7, 0.6, 0.5), 0.0, 0.0);
         world.objects.push(Box::new(Sphere {
             center: Point3::new(4.0, 1.0, 0.0),
             radius: 1.0,
@@ -208,5 +431,38 @@ pub fn random_scene() -> HittableList {
         }));
     }
 
-    return world;
+    if string_lights {
+        add_string_lights(&mut world, &mut lights);
+    }
+    (world, lights)
+}
+
+/// Strands of bulbs sagging between posts at both sides of the scene.
+fn add_string_lights(world: &mut HittableList, lights: &mut Vec<Light>) {
+    let bulb_colors = [
+        Color::new(1.0, 0.75, 0.4),
+        Color::new(1.0, 0.3, 0.2),
+        Color::new(0.3, 1.0, 0.4),
+        Color::new(0.3, 0.5, 1.0),
+    ];
+    let bulbs_per_strand = 60;
+    for x in [-9.0, -5.0, -1.0, 3.0] {
+        for i in 0..bulbs_per_strand {
+            // From one post at z = -9 to the other at z = 9
+            let s = 2.0 * i as f64 / (bulbs_per_strand - 1) as f64 - 1.0;
+            let center = Point3::new(x, 3.4 - 0.9 * (1.0 - s * s), 9.0 * s);
+            let color = bulb_colors[(random_double() * bulb_colors.len() as f64) as usize];
+            add_object(
+                world,
+                lights,
+                Shape::Sphere {
+                    center,
+                    radius: 0.05,
+                },
+                MaterialEnum::Emissive {
+                    radiance: 4.0 * color,
+                },
+            );
+        }
+    }
 }